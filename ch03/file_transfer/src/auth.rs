@@ -0,0 +1,164 @@
+use std::fmt;
+use std::future::{ready, Ready};
+use std::io;
+use std::path::PathBuf;
+
+use actix_web::http::header::{self, Header};
+use actix_web::http::StatusCode;
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic};
+use persons_api::models::{DbPrivilege, User};
+use serde_derive::Deserialize;
+
+use crate::storage::Storage;
+
+// Directory of the storage root containing the home directories of the users.
+const HOME_DIR: &str = "home";
+
+// A user as in the database, with the limit of the space they can take.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserSettings {
+    #[serde(flatten)]
+    pub user: User,
+    // Maximum total size in bytes of the files in the home directory of the user.
+    pub quota: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthSettings {
+    pub users: Vec<UserSettings>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthorized(String),
+    Forbidden(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Unauthorized(msg) | AuthError::Forbidden(msg) => write!(f, "{}", msg),
+            AuthError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AuthError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        log::warn!("Access denied: {}", self);
+        match self {
+            AuthError::Unauthorized(msg) => HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"FileTransfer\""))
+                .body(msg.clone()),
+            AuthError::Forbidden(msg) => HttpResponse::Forbidden().body(msg.clone()),
+            AuthError::Io(_) => HttpResponse::InternalServerError().finish(),
+        }
+    }
+}
+
+pub struct Users {
+    users: Vec<UserSettings>,
+    // Directory containing the home directories.
+    homes: PathBuf,
+}
+
+impl Users {
+    pub fn new(settings: AuthSettings, storage: &Storage) -> Result<Users, String> {
+        for UserSettings { user, .. } in &settings.users {
+            // The username is the name of the home directory.
+            if user.username.is_empty()
+                || user.username.starts_with('.')
+                || user.username.contains(['/', '\\', '\0'])
+            {
+                return Err(format!("invalid username \"{}\"", user.username));
+            }
+        }
+        Ok(Users {
+            users: settings.users,
+            homes: storage.root().join(HOME_DIR),
+        })
+    }
+
+    fn authenticate(&self, credentials: &Basic) -> Result<UserSettings, AuthError> {
+        match self
+            .users
+            .iter()
+            .find(|u| u.user.username == credentials.user_id())
+        {
+            Some(settings) if credentials.password() == Some(settings.user.password.as_str()) => {
+                Ok(settings.clone())
+            }
+            Some(settings) => Err(AuthError::Unauthorized(format!(
+                "Invalid password for user \"{}\".",
+                settings.user.username
+            ))),
+            None => Err(AuthError::Unauthorized(format!(
+                "User \"{}\" not found.",
+                credentials.user_id()
+            ))),
+        }
+    }
+}
+
+// The user authenticated by the credentials of a request.
+pub struct Account {
+    user: User,
+    quota: Option<u64>,
+    homes: PathBuf,
+}
+
+impl Account {
+    // Checks the privilege of the user,
+    // and returns the user with the storage of their home directory.
+    pub fn authorize(&self, required_privilege: DbPrivilege) -> Result<(User, Storage), AuthError> {
+        if !self.user.can(required_privilege) {
+            return Err(AuthError::Forbidden(format!(
+                "Insufficient privileges for user \"{}\".",
+                self.user.username
+            )));
+        }
+        let home = Storage::open(&self.homes.join(&self.user.username)).map_err(AuthError::Io)?;
+        Ok((self.user.clone(), home))
+    }
+}
+
+impl FromRequest for Account {
+    type Error = AuthError;
+    type Future = Ready<Result<Account, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let users = req
+            .app_data::<web::Data<Users>>()
+            .expect("the users are not configured");
+        ready(
+            Authorization::<Basic>::parse(req)
+                .map_err(|_| AuthError::Unauthorized("Missing credentials.".to_string()))
+                .and_then(|auth| users.authenticate(auth.as_ref()))
+                .map(|settings| Account {
+                    user: settings.user,
+                    quota: settings.quota,
+                    homes: users.homes.clone(),
+                }),
+        )
+    }
+}
+
+impl Account {
+    // How many bytes the user can still store, if they have a quota.
+    // `used` is the space taken by the user,
+    // and `freed` the space that storing the file would free.
+    pub fn quota_left(&self, used: u64, freed: u64) -> Option<u64> {
+        self.quota.map(|quota| (quota + freed).saturating_sub(used))
+    }
+}
@@ -0,0 +1,710 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::history::{HistorySettings, TrashEntry, Version};
+use crate::upload::PartialFile;
+
+// Directory of the blob store, inside the storage root.
+pub const STORE_DIR: &str = ".store";
+const CATALOG_FILE: &str = "catalog.json";
+
+// A file of the storage, with the hash of its contents.
+// The time it was stored is kept here, as the modification time of the file
+// is that of the blob, shared by all the files having the same contents.
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredFile {
+    sha256: String,
+    stored_at: DateTime<Utc>,
+    // Taken from the blob when opening the store.
+    #[serde(skip)]
+    size: u64,
+}
+
+// The files of the storage, identified by their path relative to the root,
+// with their previous versions, the deleted files,
+// and how many of them have each content.
+#[derive(Default, Serialize, Deserialize)]
+struct Catalog {
+    files: BTreeMap<String, StoredFile>,
+    versions: BTreeMap<String, Vec<Version>>,
+    trash: Vec<TrashEntry>,
+    #[serde(skip)]
+    refs: HashMap<String, u32>,
+    // The total size of the files under each directory, kept up to date as they change,
+    // so that the quotas are checked without walking the directories.
+    #[serde(skip)]
+    dir_sizes: HashMap<String, u64>,
+    // The size and modification time of the blobs when their contents were last
+    // found to match their hash, not to hash them again until they change.
+    #[serde(skip)]
+    verified: HashMap<String, (u64, SystemTime)>,
+}
+
+impl Catalog {
+    fn add_size(&mut self, key: &str, size: u64) {
+        for dir in Path::new(key).ancestors().skip(1) {
+            *self
+                .dir_sizes
+                .entry(dir.to_string_lossy().into_owned())
+                .or_default() += size;
+        }
+    }
+
+    fn remove_size(&mut self, key: &str, size: u64) {
+        for dir in Path::new(key).ancestors().skip(1) {
+            if let Some(total) = self.dir_sizes.get_mut(&*dir.to_string_lossy()) {
+                *total = total.saturating_sub(size);
+            }
+        }
+    }
+}
+
+// Stores each distinct content once, as a blob named after its SHA-256 hash.
+// The files of the storage are hard links to the blobs, and a blob is deleted
+// when no file, previous version or deleted file has its content anymore.
+pub struct BlobStore {
+    root: PathBuf,
+    dir: PathBuf,
+    settings: HistorySettings,
+    catalog: Mutex<Catalog>,
+}
+
+fn blob_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join("blobs").join(&hash[..2]).join(hash)
+}
+
+pub fn sha256_of_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl BlobStore {
+    pub fn open(root: &Path, settings: HistorySettings) -> io::Result<BlobStore> {
+        let dir = root.join(STORE_DIR);
+        std::fs::create_dir_all(dir.join("blobs"))?;
+        std::fs::create_dir_all(dir.join("tmp"))?;
+        let mut catalog = match std::fs::read(dir.join(CATALOG_FILE)) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Catalog::default(),
+            Err(e) => return Err(e),
+        };
+        let hashes = catalog
+            .files
+            .values()
+            .map(|file| &file.sha256)
+            .chain(catalog.versions.values().flatten().map(|v| &v.sha256))
+            .chain(catalog.trash.iter().map(|entry| &entry.sha256))
+            .cloned()
+            .collect::<Vec<_>>();
+        for hash in hashes {
+            *catalog.refs.entry(hash).or_default() += 1;
+        }
+        let mut sizes = Vec::new();
+        for (key, file) in catalog.files.iter_mut() {
+            file.size = std::fs::metadata(blob_path(&dir, &file.sha256)).map_or(0, |m| m.len());
+            sizes.push((key.clone(), file.size));
+        }
+        for (key, size) in sizes {
+            catalog.add_size(&key, size);
+        }
+        Ok(BlobStore {
+            root: root.to_path_buf(),
+            dir,
+            settings,
+            catalog: Mutex::new(catalog),
+        })
+    }
+
+    // Where the files being uploaded are written.
+    pub fn temp_dir(&self) -> PathBuf {
+        self.dir.join("tmp")
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        blob_path(&self.dir, hash)
+    }
+
+    fn blob_size(&self, hash: &str) -> u64 {
+        std::fs::metadata(self.blob_path(hash)).map_or(0, |m| m.len())
+    }
+
+    fn key(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn save(&self, catalog: &Catalog) -> io::Result<()> {
+        let temp_path = self.dir.join(format!("{}.tmp", CATALOG_FILE));
+        std::fs::write(&temp_path, serde_json::to_vec(catalog)?)?;
+        std::fs::rename(temp_path, self.dir.join(CATALOG_FILE))
+    }
+
+    fn release(&self, catalog: &mut Catalog, hash: &str) -> io::Result<()> {
+        let refs = catalog.refs.entry(hash.to_string()).or_default();
+        *refs = refs.saturating_sub(1);
+        if *refs == 0 {
+            catalog.refs.remove(hash);
+            catalog.verified.remove(hash);
+            match std::fs::remove_file(self.blob_path(hash)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps the replaced content of a file as its newest version,
+    // dropping the oldest versions beyond the configured number.
+    fn keep_version(
+        &self,
+        catalog: &mut Catalog,
+        key: &str,
+        replaced: String,
+        current: &str,
+    ) -> io::Result<()> {
+        if replaced == current {
+            return self.release(catalog, &replaced);
+        }
+        let size = self.blob_size(&replaced);
+        let versions = catalog.versions.entry(key.to_string()).or_default();
+        versions.push(Version {
+            number: versions.last().map_or(1, |v| v.number + 1),
+            sha256: replaced,
+            size,
+            replaced_at: Utc::now(),
+        });
+        let excess = versions.len().saturating_sub(self.settings.max_versions);
+        let dropped = versions.drain(..excess).collect::<Vec<_>>();
+        for version in dropped {
+            self.release(catalog, &version.sha256)?;
+        }
+        Ok(())
+    }
+
+    // Moves a deleted file to the trash, unless the trash is disabled.
+    fn discard(&self, catalog: &mut Catalog, key: String, hash: String) -> io::Result<()> {
+        if self.settings.trash_retention_days == 0 {
+            return self.release(catalog, &hash);
+        }
+        let deleted_at = Utc::now();
+        catalog.trash.push(TrashEntry {
+            id: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+            path: key,
+            size: self.blob_size(&hash),
+            sha256: hash,
+            deleted_at,
+            expires_at: deleted_at + Duration::days(self.settings.trash_retention_days as i64),
+        });
+        Ok(())
+    }
+
+    // Links `destination` to the existing blob `hash`, replacing the existing file
+    // only if `replace` is true, and records it in the catalog, which is not saved.
+    fn link(
+        &self,
+        catalog: &mut Catalog,
+        hash: &str,
+        destination: &Path,
+        replace: bool,
+    ) -> io::Result<()> {
+        let blob_path = self.blob_path(hash);
+        if replace {
+            // The temporary link is not left behind, as it would prevent storing the contents again.
+            // It is still there also after renaming it over a link to the same blob,
+            // as then the renaming does nothing.
+            let temp_path = self.temp_dir().join(format!("{}.link", hash));
+            let _ = std::fs::remove_file(&temp_path);
+            let linked = std::fs::hard_link(&blob_path, &temp_path)
+                .and_then(|_| std::fs::rename(&temp_path, destination));
+            let _ = std::fs::remove_file(&temp_path);
+            linked?;
+        } else {
+            std::fs::hard_link(&blob_path, destination)?;
+        }
+
+        *catalog.refs.entry(hash.to_string()).or_default() += 1;
+        let key = self.key(destination);
+        let stored = StoredFile {
+            sha256: hash.to_string(),
+            stored_at: Utc::now(),
+            size: self.blob_size(hash),
+        };
+        catalog.add_size(&key, stored.size);
+        if let Some(previous) = catalog.files.insert(key.clone(), stored) {
+            catalog.remove_size(&key, previous.size);
+            self.keep_version(catalog, &key, previous.sha256, hash)?;
+        }
+        Ok(())
+    }
+
+    // The hash of the contents of the file at `path`, if it is in the store.
+    pub fn hash_of(&self, path: &Path) -> Option<String> {
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .files
+            .get(&self.key(path))
+            .map(|file| file.sha256.clone())
+    }
+
+    // The hash of the current contents of the file at `path`, which is in the store
+    // with the contents of hash `hash`. They are hashed again only if the file
+    // has changed since they were last found to match.
+    pub fn checked_hash(&self, path: &Path, hash: &str) -> io::Result<String> {
+        let metadata = std::fs::metadata(path)?;
+        let stamp = (metadata.len(), metadata.modified()?);
+        if self.catalog.lock().unwrap().verified.get(hash) == Some(&stamp) {
+            return Ok(hash.to_string());
+        }
+        let actual = sha256_of_file(path)?;
+        if actual == hash {
+            let catalog = &mut *self.catalog.lock().unwrap();
+            if catalog.refs.contains_key(hash) {
+                catalog.verified.insert(actual.clone(), stamp);
+            }
+        }
+        Ok(actual)
+    }
+
+    // When the file at `path` was stored, if it is in the store.
+    pub fn stored_at(&self, path: &Path) -> Option<DateTime<Utc>> {
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .files
+            .get(&self.key(path))
+            .map(|file| file.stored_at)
+    }
+
+    // Stores the contents of `file` at `destination`, replacing the existing file
+    // only if `replace` is true, and returns their hash.
+    pub fn put(&self, file: &PartialFile, destination: &Path, replace: bool) -> io::Result<String> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        let hash = file.sha256().to_string();
+        let blob_path = self.blob_path(&hash);
+        let new_blob = !catalog.refs.contains_key(&hash);
+        if new_blob {
+            std::fs::create_dir_all(blob_path.parent().unwrap())?;
+            // Replaces any blob left over by an interrupted deletion.
+            let _ = std::fs::remove_file(&blob_path);
+            std::fs::hard_link(file.path(), &blob_path)?;
+        }
+        if let Err(e) = self.link(catalog, &hash, destination, replace) {
+            if new_blob && !catalog.refs.contains_key(&hash) {
+                let _ = std::fs::remove_file(&blob_path);
+            }
+            return Err(e);
+        }
+        self.save(catalog)?;
+        Ok(hash)
+    }
+
+    // Deletes the file at `path`, keeping it in the trash.
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        std::fs::remove_file(path)?;
+        let key = self.key(path);
+        if let Some(file) = catalog.files.remove(&key) {
+            catalog.remove_size(&key, file.size);
+            self.discard(catalog, key, file.sha256)?;
+            self.save(catalog)?;
+        }
+        Ok(())
+    }
+
+    // Deletes the directory at `path`, with all its contents if `recursive` is true.
+    // The files it contained are kept in the trash.
+    pub fn remove_dir(&self, path: &Path, recursive: bool) -> io::Result<()> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        if recursive {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_dir(path)?;
+        }
+        let prefix = format!("{}/", self.key(path));
+        let removed = catalog
+            .files
+            .keys()
+            .filter(|k| k.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in &removed {
+            if let Some(file) = catalog.files.remove(key) {
+                catalog.remove_size(key, file.size);
+                self.discard(catalog, key.clone(), file.sha256)?;
+            }
+        }
+        if !removed.is_empty() {
+            self.save(catalog)?;
+        }
+        Ok(())
+    }
+
+    // The previous versions of the file at `path`, oldest first.
+    pub fn versions(&self, path: &Path) -> Vec<Version> {
+        let catalog = self.catalog.lock().unwrap();
+        catalog
+            .versions
+            .get(&self.key(path))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Makes the version `number` of the file at `path` its current content,
+    // keeping the replaced content as a new version, and returns its hash.
+    pub fn restore_version(&self, path: &Path, number: u64) -> io::Result<String> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        let hash = catalog
+            .versions
+            .get(&self.key(path))
+            .and_then(|versions| versions.iter().find(|v| v.number == number))
+            .map(|version| version.sha256.clone())
+            .ok_or(io::ErrorKind::NotFound)?;
+        self.link(catalog, &hash, path, true)?;
+        self.save(catalog)?;
+        Ok(hash)
+    }
+
+    // The space taken by the files under `dir` which are in the store,
+    // that is all of them but those put there by other means than the server.
+    pub fn files_size(&self, dir: &Path) -> u64 {
+        let catalog = self.catalog.lock().unwrap();
+        catalog.dir_sizes.get(&self.key(dir)).copied().unwrap_or(0)
+    }
+
+    // The space taken by the previous versions and the deleted files
+    // of the files under `dir`, which count towards the quota of its owner.
+    pub fn history_size(&self, dir: &Path) -> u64 {
+        let catalog = self.catalog.lock().unwrap();
+        let prefix = format!("{}/", self.key(dir));
+        let versions = catalog
+            .versions
+            .iter()
+            .filter(|(key, _)| key.starts_with(&prefix))
+            .flat_map(|(_, versions)| versions)
+            .map(|version| version.size);
+        let trash = catalog
+            .trash
+            .iter()
+            .filter(|entry| entry.path.starts_with(&prefix))
+            .map(|entry| entry.size);
+        versions.chain(trash).sum()
+    }
+
+    // The space freed by replacing the file at `path`, as its content is kept as a version,
+    // unless no versions are kept, possibly making room by dropping the oldest one.
+    pub fn freed_by_replacing(&self, path: &Path) -> u64 {
+        if self.settings.max_versions == 0 {
+            return match path.symlink_metadata() {
+                Ok(metadata) if metadata.is_file() => metadata.len(),
+                _ => 0,
+            };
+        }
+        let catalog = self.catalog.lock().unwrap();
+        match catalog.versions.get(&self.key(path)) {
+            Some(versions) if versions.len() >= self.settings.max_versions => {
+                versions.first().map_or(0, |version| version.size)
+            }
+            _ => 0,
+        }
+    }
+
+    // The files of the trash which were deleted from under `dir`,
+    // with their paths relative to it, most recently deleted first.
+    pub fn trash(&self, dir: &Path) -> Vec<TrashEntry> {
+        let catalog = self.catalog.lock().unwrap();
+        let prefix = format!("{}/", self.key(dir));
+        catalog
+            .trash
+            .iter()
+            .rev()
+            .filter_map(|entry| {
+                entry.path.strip_prefix(&prefix).map(|path| TrashEntry {
+                    path: path.to_string(),
+                    ..entry.clone()
+                })
+            })
+            .collect()
+    }
+
+    fn trash_position(&self, catalog: &Catalog, dir: &Path, id: &str) -> io::Result<usize> {
+        let prefix = format!("{}/", self.key(dir));
+        catalog
+            .trash
+            .iter()
+            .position(|entry| entry.id == id && entry.path.starts_with(&prefix))
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    // Puts the file `id` of the trash of `dir` back where it was,
+    // recreating its parent directories, unless another file has taken its place.
+    pub fn restore_deleted(&self, dir: &Path, id: &str) -> io::Result<String> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        let n = self.trash_position(catalog, dir, id)?;
+        let entry = catalog.trash[n].clone();
+        let destination = self.root.join(&entry.path);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        self.link(catalog, &entry.sha256, &destination, false)?;
+        catalog.trash.remove(n);
+        self.release(catalog, &entry.sha256)?;
+        self.save(catalog)?;
+        Ok(entry.sha256)
+    }
+
+    // Deletes permanently the file `id` of the trash of `dir`, or all of them,
+    // and returns how many they were.
+    pub fn empty_trash(&self, dir: &Path, id: Option<&str>) -> io::Result<usize> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        let prefix = format!("{}/", self.key(dir));
+        let removed = match id {
+            Some(id) => {
+                let n = self.trash_position(catalog, dir, id)?;
+                vec![catalog.trash.remove(n)]
+            }
+            None => {
+                let (removed, kept) = std::mem::take(&mut catalog.trash)
+                    .into_iter()
+                    .partition(|entry| entry.path.starts_with(&prefix));
+                catalog.trash = kept;
+                removed
+            }
+        };
+        self.forget(catalog, &removed)?;
+        Ok(removed.len())
+    }
+
+    // Deletes permanently the expired files of the trash, and returns how many they were.
+    pub fn purge(&self, now: DateTime<Utc>) -> io::Result<usize> {
+        let catalog = &mut *self.catalog.lock().unwrap();
+        let (expired, kept) = std::mem::take(&mut catalog.trash)
+            .into_iter()
+            .partition(|entry| entry.expires_at <= now);
+        catalog.trash = kept;
+        self.forget(catalog, &expired)?;
+        Ok(expired.len())
+    }
+
+    // Releases the contents of the files removed from the trash,
+    // and the versions of the files which no longer exist anywhere.
+    fn forget(&self, catalog: &mut Catalog, removed: &[TrashEntry]) -> io::Result<()> {
+        for entry in removed {
+            self.release(catalog, &entry.sha256)?;
+        }
+        let orphans = catalog
+            .versions
+            .keys()
+            .filter(|key| {
+                !catalog.files.contains_key(*key)
+                    && !catalog.trash.iter().any(|entry| entry.path == **key)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in &orphans {
+            for version in catalog.versions.remove(key).unwrap_or_default() {
+                self.release(catalog, &version.sha256)?;
+            }
+        }
+        if !removed.is_empty() || !orphans.is_empty() {
+            self.save(catalog)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A scratch directory holding the storage root of a test.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Scratch {
+            let dir = std::env::temp_dir().join(format!("file_transfer-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(dir.join("home")).unwrap();
+            Scratch(dir)
+        }
+
+        fn store(&self, max_versions: usize, trash_retention_days: u64) -> BlobStore {
+            let settings = HistorySettings {
+                max_versions,
+                trash_retention_days,
+                ..HistorySettings::default()
+            };
+            BlobStore::open(&self.0, settings).unwrap()
+        }
+
+        fn path(&self, relative: &str) -> PathBuf {
+            self.0.join(relative)
+        }
+
+        fn blob_count(&self) -> usize {
+            std::fs::read_dir(self.0.join(STORE_DIR).join("blobs"))
+                .unwrap()
+                .map(|dir| std::fs::read_dir(dir.unwrap().path()).unwrap().count())
+                .sum()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Stores `contents` at `relative`, replacing the existing file.
+    fn put(store: &BlobStore, scratch: &Scratch, relative: &str, contents: &str) -> String {
+        let temp_path = store.temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::write(&temp_path, contents).unwrap();
+        let file = PartialFile::new(temp_path.clone(), sha256_of_file(&temp_path).unwrap());
+        let destination = scratch.path(relative);
+        std::fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        store.put(&file, &destination, true).unwrap()
+    }
+
+    fn refs(store: &BlobStore, hash: &str) -> u32 {
+        store
+            .catalog
+            .lock()
+            .unwrap()
+            .refs
+            .get(hash)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn same_contents_are_stored_once() {
+        let scratch = Scratch::new();
+        let store = scratch.store(0, 0);
+        let hash = put(&store, &scratch, "home/a.txt", "hello");
+        assert_eq!(put(&store, &scratch, "home/b.txt", "hello"), hash);
+        assert_eq!(refs(&store, &hash), 2);
+        assert_eq!(scratch.blob_count(), 1);
+        assert_eq!(store.files_size(&scratch.path("home")), 10);
+
+        store.remove_file(&scratch.path("home/a.txt")).unwrap();
+        assert_eq!(refs(&store, &hash), 1);
+        assert_eq!(scratch.blob_count(), 1);
+        store.remove_file(&scratch.path("home/b.txt")).unwrap();
+        assert_eq!(refs(&store, &hash), 0);
+        assert_eq!(scratch.blob_count(), 0);
+        assert_eq!(store.files_size(&scratch.path("home")), 0);
+    }
+
+    #[test]
+    fn replaced_contents_are_kept_as_versions() {
+        let scratch = Scratch::new();
+        let store = scratch.store(2, 0);
+        for contents in ["one", "two", "three", "four"] {
+            put(&store, &scratch, "home/a.txt", contents);
+        }
+        let path = scratch.path("home/a.txt");
+        let versions = store.versions(&path);
+        assert_eq!(
+            versions.iter().map(|v| v.number).collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert_eq!(scratch.blob_count(), 3);
+        assert_eq!(store.history_size(&scratch.path("home")), 3 + 5);
+        assert_eq!(store.files_size(&scratch.path("home")), 4);
+        // The oldest version is dropped by the next replacement.
+        assert_eq!(store.freed_by_replacing(&path), 3);
+
+        store.restore_version(&path, 2).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(
+            store
+                .versions(&path)
+                .iter()
+                .map(|v| v.number)
+                .collect::<Vec<_>>(),
+            [3, 4]
+        );
+        assert_eq!(store.files_size(&scratch.path("home")), 3);
+        assert_eq!(scratch.blob_count(), 3);
+    }
+
+    #[test]
+    fn catalog_is_reloaded() {
+        let scratch = Scratch::new();
+        let store = scratch.store(10, 30);
+        let hash = put(&store, &scratch, "home/a.txt", "hello");
+        put(&store, &scratch, "home/a.txt", "hello, world");
+        put(&store, &scratch, "home/dir/b.txt", "hello");
+        store.remove_dir(&scratch.path("home/dir"), true).unwrap();
+        drop(store);
+
+        let store = scratch.store(10, 30);
+        let home = scratch.path("home");
+        assert!(store.hash_of(&scratch.path("home/a.txt")).is_some());
+        assert_eq!(store.versions(&scratch.path("home/a.txt"))[0].sha256, hash);
+        let trash = store.trash(&home);
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].path, "dir/b.txt");
+        assert_eq!(store.files_size(&home), 12);
+        assert_eq!(store.history_size(&home), 10);
+        // The version and the deleted file share their blob.
+        assert_eq!(refs(&store, &hash), 2);
+
+        store.restore_deleted(&home, &trash[0].id).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(scratch.path("home/dir/b.txt")).unwrap(),
+            "hello"
+        );
+        assert_eq!(store.files_size(&home), 17);
+    }
+
+    #[test]
+    fn purge_deletes_only_the_expired_files() {
+        let scratch = Scratch::new();
+        let store = scratch.store(10, 30);
+        put(&store, &scratch, "home/a.txt", "old");
+        put(&store, &scratch, "home/a.txt", "new");
+        put(&store, &scratch, "home/b.txt", "kept");
+        store.remove_file(&scratch.path("home/a.txt")).unwrap();
+        assert_eq!(scratch.blob_count(), 3);
+
+        let now = Utc::now();
+        assert_eq!(store.purge(now).unwrap(), 0);
+        assert_eq!(store.trash(&scratch.path("home")).len(), 1);
+        assert_eq!(store.purge(now + Duration::days(31)).unwrap(), 1);
+        assert!(store.trash(&scratch.path("home")).is_empty());
+        // The versions of the purged file go with it.
+        assert!(store.versions(&scratch.path("home/a.txt")).is_empty());
+        assert_eq!(scratch.blob_count(), 1);
+    }
+
+    #[test]
+    fn checked_hash_notices_modified_files() {
+        let scratch = Scratch::new();
+        let store = scratch.store(0, 0);
+        let hash = put(&store, &scratch, "home/a.txt", "hello");
+        let path = scratch.path("home/a.txt");
+        assert_eq!(store.checked_hash(&path, &hash).unwrap(), hash);
+        assert!(store.catalog.lock().unwrap().verified.contains_key(&hash));
+
+        std::fs::write(&path, "hello, world").unwrap();
+        assert_ne!(store.checked_hash(&path, &hash).unwrap(), hash);
+    }
+}
@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistorySettings {
+    // Number of previous versions kept for each file.
+    pub max_versions: usize,
+    // Days during which the deleted files can be restored from the trash.
+    pub trash_retention_days: u64,
+    // Time between two purges of the expired files of the trash.
+    pub purge_interval_minutes: u64,
+}
+
+impl Default for HistorySettings {
+    fn default() -> HistorySettings {
+        HistorySettings {
+            max_versions: 10,
+            trash_retention_days: 30,
+            purge_interval_minutes: 60,
+        }
+    }
+}
+
+// A previous content of a file, replaced by a newer one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Version {
+    // Increasing with each version of the file.
+    pub number: u64,
+    pub sha256: String,
+    pub size: u64,
+    pub replaced_at: DateTime<Utc>,
+}
+
+// A deleted file, which can be restored until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    // Relative to the storage root in the store,
+    // and to the directory whose trash is listed in the responses.
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+    pub deleted_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
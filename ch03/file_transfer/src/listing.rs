@@ -0,0 +1,55 @@
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde_derive::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+// Lists the entries of a directory sorted by name.
+// Hidden entries, like the files still being uploaded, are left out.
+// The files known by `stored_at` are reported as modified when they were stored.
+pub fn list_directory(
+    dir: &Path,
+    stored_at: impl Fn(&Path) -> Option<DateTime<Utc>>,
+) -> io::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    for dir_entry in std::fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        // Skips the links that cannot be followed.
+        let Ok(metadata) = std::fs::metadata(dir_entry.path()) else {
+            continue;
+        };
+        entries.push(Entry {
+            name,
+            entry_type: if metadata.is_dir() {
+                EntryType::Directory
+            } else {
+                EntryType::File
+            },
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: stored_at(&dir_entry.path())
+                .or_else(|| metadata.modified().ok().map(DateTime::from)),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
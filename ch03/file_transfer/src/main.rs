@@ -1,160 +1,794 @@
-use actix_web::Error;
+mod auth;
+mod blobs;
+mod history;
+mod listing;
+mod naming;
+mod resumable;
+mod storage;
+mod upload;
+
+use actix_cors::Cors;
+use actix_files::NamedFile;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::Method;
 use actix_web::{
     web::{self, Path},
-    App, HttpRequest, HttpResponse, HttpServer, Responder,
+    App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
 };
-use futures::future::{ok, Future};
-use futures::{TryFutureExt, TryStreamExt};
-use rand::Rng;
-use std::fs::OpenOptions;
-use std::io::{self, Read, Write};
+use auth::{Account, AuthSettings, Users};
+use blobs::BlobStore;
+use chrono::Utc;
+use history::HistorySettings;
+use naming::Namer;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use persons_api::models::{DbPrivilege, User};
+use resumable::{NewSession, ResumableError, ResumableUploads};
+use serde_derive::Deserialize;
+use server_config::Config;
+use server_telemetry::Telemetry;
+use std::path::{Path as FsPath, PathBuf};
+use std::time::{Duration, SystemTime};
+use storage::{Storage, StorageError};
+use upload::{UploadError, UploadSettings};
+
+// Header carrying the hex SHA-256 hash of a chunk of a resumable upload.
+const CHUNK_SHA256_HEADER: &str = "x-chunk-sha256";
+// Characters escaped in the segments of the URL paths returned to clients.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn rejected_path(error: StorageError) -> HttpResponse {
+    log::warn!("Rejected the path: {}", error);
+    match error {
+        StorageError::InvalidPath(_) => HttpResponse::BadRequest().finish(),
+        StorageError::OutsideRoot(_) => HttpResponse::Forbidden().finish(),
+        StorageError::Io(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
 
-fn flush_stdout() {
-    io::stdout().flush().unwrap();
+#[derive(Deserialize)]
+pub struct DeleteQuery {
+    recursive: Option<bool>,
 }
 
-async fn delete_file(info: Path<(String,)>) -> impl Responder {
+async fn delete_file(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    info: Path<(String,)>,
+    query: web::Query<DeleteQuery>,
+) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanDelete) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
     let filename = &info.0;
-    print!("Deleting file \"{}\" ... ", filename);
-    flush_stdout();
+    let path = match storage.resolve(filename) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
 
-    match std::fs::remove_file(&filename) {
+    // Non-empty directories are deleted only if explicitly requested.
+    let result = match std::fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => {
+            blobs.remove_dir(&path, query.recursive.unwrap_or(false))
+        }
+        Ok(_) => blobs.remove_file(&path),
+        Err(error) => Err(error),
+    };
+    match result {
         Ok(_) => {
-            println!("Deleted file \"{}\"", filename);
-            HttpResponse::Ok()
+            log::info!("Deleted \"{}\"", filename);
+            HttpResponse::Ok().finish()
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::DirectoryNotEmpty => {
+            log::warn!("Failed to delete \"{}\": {}", filename, error);
+            HttpResponse::Conflict().body("The directory is not empty.")
         }
         Err(error) => {
-            println!("Failed to delete file \"{}\": {}", filename, error);
-            HttpResponse::NotFound()
+            log::warn!("Failed to delete \"{}\": {}", filename, error);
+            HttpResponse::NotFound().finish()
         }
     }
 }
 
-async fn download_file(info: Path<(String,)>) -> impl Responder {
+// Returns the contents of a file, or the entries of a directory as JSON.
+// Also serves the HEAD requests, whose responses have the same headers but no body.
+async fn download_file(
+    req: HttpRequest,
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanRead) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
     let filename = &info.0;
-    print!("Downloading file \"{}\" ... ", filename);
-    flush_stdout();
+    let path = if filename.is_empty() {
+        storage.root().to_path_buf()
+    } else {
+        match storage.resolve(filename) {
+            Ok(path) => path,
+            Err(error) => return rejected_path(error),
+        }
+    };
 
-    fn read_file_contents(filename: &str) -> std::io::Result<String> {
-        let mut contents = String::new();
-        std::fs::File::open(filename)?.read_to_string(&mut contents)?;
-        Ok(contents)
+    if path.is_dir() {
+        return match listing::list_directory(&path, |p| blobs.stored_at(p)) {
+            Ok(entries) => {
+                log::info!("Listed directory \"{}\"", filename);
+                HttpResponse::Ok().json(entries)
+            }
+            Err(error) => {
+                log::warn!("Failed to list directory \"{}\": {}", filename, error);
+                HttpResponse::NotFound().finish()
+            }
+        };
     }
 
-    match read_file_contents(&filename) {
-        Ok(contents) => {
-            println!("Downloaded file \"{}\"", filename);
-            HttpResponse::Ok().content_type("text/plain").body(contents)
+    // Serves the file in chunks, with its content type guessed from its extension,
+    // and supports range requests so that interrupted downloads can be resumed.
+    // The files in the blob store are tagged with the hash of their contents,
+    // which are checked against it before being sent whole.
+    let hash = blobs.hash_of(&path);
+    let stored_at = blobs.stored_at(&path);
+    let etag = hash.as_ref().map(|hash| format!("\"{}\"", hash));
+    if let Some(etag) = &etag {
+        if if_none_match(&req, etag) {
+            return HttpResponse::NotModified()
+                .insert_header((header::ETAG, etag.as_str()))
+                .finish();
+        }
+    }
+    let whole = req.method() != Method::HEAD && !req.headers().contains_key(header::RANGE);
+    if let (Some(hash), true) = (&hash, whole) {
+        let (file_path, expected, blobs) = (path.clone(), hash.clone(), blobs.clone());
+        match web::block(move || blobs.checked_hash(&file_path, &expected)).await {
+            Ok(Ok(actual)) if actual == *hash => {}
+            Ok(Ok(actual)) => {
+                log::error!(
+                    "File \"{}\" is corrupted: expected hash {}, found {}",
+                    filename,
+                    hash,
+                    actual
+                );
+                return HttpResponse::InternalServerError().finish();
+            }
+            _ => return HttpResponse::NotFound().finish(),
+        }
+    }
+
+    match NamedFile::open_async(&path).await {
+        Ok(file) => {
+            log::info!("Downloaded file \"{}\"", filename);
+            let mut response = file
+                .use_etag(etag.is_none())
+                .use_last_modified(stored_at.is_none())
+                .into_response(&req);
+            if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+                response.headers_mut().insert(header::ETAG, etag);
+            }
+            // The modification time of the file is that of its blob.
+            let last_modified = stored_at
+                .map(|time| header::HttpDate::from(SystemTime::from(time)).to_string())
+                .and_then(|date| HeaderValue::from_str(&date).ok());
+            if let Some(last_modified) = last_modified {
+                response
+                    .headers_mut()
+                    .insert(header::LAST_MODIFIED, last_modified);
+            }
+            response
         }
         Err(error) => {
-            println!("Failed to read file \"{}\": {}", filename, error);
+            log::warn!("Failed to read file \"{}\": {}", filename, error);
             HttpResponse::NotFound().finish()
         }
     }
 }
 
-async fn upload_specific_file(payload: web::Payload, info: Path<(String,)>) -> impl Responder {
-    let filename = info.0.clone();
-
-    print!("Uploading file \"{}\" ... ", filename);
-    flush_stdout();
+// Creates a directory, whose parent must exist, like the WebDAV MKCOL method.
+async fn make_directory(account: Account, info: Path<(String,)>) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let dirname = &info.0;
+    let path = match storage.resolve(dirname) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
 
-    payload
-        .map_err(Error::from)
-        .try_fold(web::BytesMut::new(), |mut body, chunk| async move {
-            body.extend_from_slice(&chunk);
-            Ok::<_, Error>(body)
-        })
-        .and_then(|contents| async move {
-            let f = std::fs::File::create(&filename);
-            if f.is_err() {
-                println!("Failed to create file \"{}\"", filename);
-                return Ok::<_, Error>(HttpResponse::NotFound().into());
+    match std::fs::create_dir(&path) {
+        Ok(_) => {
+            log::info!("Created directory \"{}\"", dirname);
+            HttpResponse::Created().finish()
+        }
+        Err(error) => {
+            log::warn!("Failed to create directory \"{}\": {}", dirname, error);
+            match error.kind() {
+                std::io::ErrorKind::AlreadyExists => HttpResponse::MethodNotAllowed().finish(),
+                std::io::ErrorKind::NotFound => HttpResponse::Conflict().finish(),
+                _ => HttpResponse::InternalServerError().finish(),
             }
+        }
+    }
+}
 
-            if f.unwrap().write_all(&contents).is_err() {
-                println!("Failed to write file \"{}\"", filename);
-                return Ok(HttpResponse::NotFound().into());
-            }
+// Whether the `If-None-Match` header of the request matches the entity tag.
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
 
-            println!("Uploaded file\"{}\"", filename);
-            Ok(HttpResponse::Ok().finish())
-        })
-        .await
-}
-
-async fn upload_new_file(payload: web::Payload, info: Path<(String,)>) -> impl Responder {
-    let filename_prefix = info.0.clone();
-    print!("Uploading file \"{}*.txt\" ... ", filename_prefix);
-    flush_stdout();
-
-    payload
-        .map_err(Error::from)
-        .try_fold(web::BytesMut::new(), |mut body, chunk| async move {
-            body.extend_from_slice(&chunk);
-            Ok::<_, Error>(body)
-        })
-        .and_then(|contents| async move {
-            let mut rng = rand::thread_rng();
-            let mut attemps = 0;
-            let mut file;
-            let mut filename;
-            const MAX_ATTEMPS: u32 = 100;
+fn upload_failed(filename: &str, error: UploadError) -> HttpResponse {
+    log::warn!("Failed to upload file \"{}\": {}", filename, error);
+    match error {
+        UploadError::TooLarge(_) => HttpResponse::PayloadTooLarge().finish(),
+        UploadError::QuotaExceeded => HttpResponse::InsufficientStorage().finish(),
+        UploadError::Payload(_) => HttpResponse::BadRequest().finish(),
+        UploadError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().finish()
+        }
+        UploadError::Io(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
 
-            loop {
-                attemps += 1;
-                if attemps > MAX_ATTEMPS {
-                    println!(
-                        "Failed to create new file with prefix \"{}\", after {} attemps.",
-                        filename_prefix, MAX_ATTEMPS
-                    );
-                    return Ok(HttpResponse::NotFound().into());
-                }
+// The space taken by a user: their files, the previous versions and the deleted files
+// of their files, and their uploads in progress, except the one being finalized.
+fn used_by(
+    user: &User,
+    home: &Storage,
+    blobs: &BlobStore,
+    uploads: &ResumableUploads,
+    finalizing: Option<&str>,
+) -> u64 {
+    blobs.files_size(home.root())
+        + blobs.history_size(home.root())
+        + uploads.reserved(&user.username, finalizing)
+}
 
-                filename = format!("{}{:03}.txt", filename_prefix, rng.gen_range(0..1000));
+async fn upload_specific_file(
+    req: HttpRequest,
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    uploads: web::Data<ResumableUploads>,
+    settings: web::Data<UploadSettings>,
+    payload: web::Payload,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let filename = &info.0;
+    let path = match storage.resolve(filename) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
 
-                file = OpenOptions::new()
-                    .write(true)
-                    .create_new(true)
-                    .open(&filename);
+    let used = used_by(&user, &storage, &blobs, &uploads, None);
+    let freed = blobs.freed_by_replacing(&path);
+    let quota_left = account.quota_left(used, freed);
+    let result =
+        match upload::receive(&req, payload, &blobs.temp_dir(), &settings, quota_left).await {
+            Ok(file) => blobs.put(&file, &path, true).map_err(UploadError::from),
+            Err(error) => Err(error),
+        };
+    match result {
+        Ok(hash) => {
+            log::info!("Uploaded file \"{}\"", filename);
+            HttpResponse::Ok()
+                .insert_header((header::ETAG, format!("\"{}\"", hash)))
+                .finish()
+        }
+        Err(error) => upload_failed(filename, error),
+    }
+}
 
-                if file.is_ok() {
-                    break;
-                }
+// Returns the URL path of a file of the storage.
+fn location(filename: &str) -> String {
+    filename
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .fold(String::new(), |location, segment| location + "/" + &segment)
+}
+
+// Stores a file under a new name generated from the path of the request,
+// which is either a directory or a file name whose stem and extension are kept.
+async fn upload_new_file(
+    req: HttpRequest,
+    account: Account,
+    (blobs, uploads): (web::Data<BlobStore>, web::Data<ResumableUploads>),
+    namer: web::Data<Namer>,
+    settings: web::Data<UploadSettings>,
+    payload: web::Payload,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let mut template = info.0.clone();
+    const MAX_ATTEMPTS: u32 = 100;
+
+    // The name is chosen once the contents are received, but the template is checked before.
+    if let Err(error) = storage.resolve(&format!("{}_", template)) {
+        return rejected_path(error);
+    }
+    if !template.is_empty() && !template.ends_with('/') {
+        if let Ok(path) = storage.resolve(&template) {
+            if path.is_dir() {
+                template.push('/');
             }
+        }
+    }
+    let used = used_by(&user, &storage, &blobs, &uploads, None);
+    let quota_left = account.quota_left(used, 0);
+    let file = match upload::receive(&req, payload, &blobs.temp_dir(), &settings, quota_left).await
+    {
+        Ok(file) => file,
+        Err(error) => return upload_failed(&template, error),
+    };
 
-            if file.unwrap().write_all(&contents).is_err() {
-                println!("Failed to write file \"{}\"", filename);
-                return Ok(HttpResponse::NotFound().into());
+    // The generated names are unlikely to exist, unless created by other clients.
+    for attempt in 0..MAX_ATTEMPTS {
+        let filename = match namer.name(&template, attempt) {
+            Ok(filename) => filename,
+            Err(error) => return upload_failed(&template, error.into()),
+        };
+        let path = match storage.resolve(&filename) {
+            Ok(path) => path,
+            Err(error) => return rejected_path(error),
+        };
+        match blobs.put(&file, &path, false) {
+            Ok(hash) => {
+                log::info!("Uploaded file \"{}\"", filename);
+                return HttpResponse::Created()
+                    .content_type("text/plain")
+                    .insert_header((header::ETAG, format!("\"{}\"", hash)))
+                    .insert_header((header::LOCATION, location(&filename)))
+                    .body(filename);
             }
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(error) => return upload_failed(&filename, error.into()),
+        }
+    }
+    log::warn!(
+        "Failed to create new file from \"{}\", after {} attempts.",
+        template,
+        MAX_ATTEMPTS
+    );
+    HttpResponse::Conflict().finish()
+}
+
+fn resumable_failed(error: ResumableError) -> HttpResponse {
+    log::warn!("Resumable upload failed: {}", error);
+    match error {
+        ResumableError::NotFound => HttpResponse::NotFound().finish(),
+        ResumableError::Invalid(msg) => HttpResponse::BadRequest().body(msg),
+        ResumableError::TooLarge(_) => HttpResponse::PayloadTooLarge().finish(),
+        ResumableError::QuotaExceeded => HttpResponse::InsufficientStorage().finish(),
+        ResumableError::ChecksumMismatch => HttpResponse::UnprocessableEntity().finish(),
+        ResumableError::Incomplete(missing) => {
+            HttpResponse::Conflict().body(format!("{} chunks are missing.", missing))
+        }
+        ResumableError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().finish()
+        }
+        ResumableError::Io(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
 
-            println!("Uploaded file \"{}\"", filename);
-            Ok(HttpResponse::Ok().content_type("text/plain").body(filename))
-        })
-        .await
+// Starts a resumable upload, whose chunks are then sent to `/.uploads/{id}/chunks/{index}`.
+async fn create_upload(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    uploads: web::Data<ResumableUploads>,
+    new_session: web::Json<NewSession>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let path = match storage.resolve(&new_session.path) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
+    let used = used_by(&user, &storage, &blobs, &uploads, None);
+    let freed = blobs.freed_by_replacing(&path);
+    let quota_left = account.quota_left(used, freed);
+    match uploads.create(&user.username, new_session.into_inner(), quota_left) {
+        Ok(session) => {
+            log::info!("Started upload {} of file \"{}\"", session.id, session.path);
+            HttpResponse::Created()
+                .insert_header((header::LOCATION, format!("/.uploads/{}", session.id)))
+                .json(resumable::Progress::from(session))
+        }
+        Err(error) => resumable_failed(error),
+    }
+}
+
+async fn upload_progress(
+    account: Account,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, _) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    match uploads.progress(&info.0, &user.username) {
+        Ok(progress) => HttpResponse::Ok().json(progress),
+        Err(error) => resumable_failed(error),
+    }
+}
+
+async fn upload_chunk(
+    req: HttpRequest,
+    account: Account,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String, u64)>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let (user, _) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let (id, index) = info.into_inner();
+    let Some(sha256) = req
+        .headers()
+        .get(CHUNK_SHA256_HEADER)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return HttpResponse::BadRequest()
+            .body(format!("The {} header is required.", CHUNK_SHA256_HEADER));
+    };
+    match uploads.write_chunk(&id, &user.username, index, sha256, &body) {
+        Ok(progress) => HttpResponse::Ok().json(progress),
+        Err(error) => resumable_failed(error),
+    }
+}
+
+// Stores the uploaded file at its destination, once all its chunks are received.
+async fn finalize_upload(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let id = &info.0;
+    let session = match uploads.progress(id, &user.username) {
+        Ok(progress) => progress.session,
+        Err(error) => return resumable_failed(error),
+    };
+    let filename = session.path;
+    let path = match storage.resolve(&filename) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
+    // Other files may have been stored since the upload started.
+    let used = used_by(&user, &storage, &blobs, &uploads, Some(id));
+    let freed = blobs.freed_by_replacing(&path);
+    if account
+        .quota_left(used, freed)
+        .is_some_and(|quota_left| session.size > quota_left)
+    {
+        return resumable_failed(ResumableError::QuotaExceeded);
+    }
+    match uploads.finalize(id, &user.username, |file| blobs.put(file, &path, true)) {
+        Ok(hash) => {
+            log::info!("Uploaded file \"{}\"", filename);
+            HttpResponse::Created()
+                .insert_header((header::ETAG, format!("\"{}\"", hash)))
+                .insert_header((header::LOCATION, location(&filename)))
+                .finish()
+        }
+        Err(error) => resumable_failed(error),
+    }
+}
+
+async fn abort_upload(
+    account: Account,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, _) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    match uploads.remove(&info.0, &user.username) {
+        Ok(()) => {
+            log::info!("Aborted upload {}", info.0);
+            HttpResponse::NoContent().finish()
+        }
+        Err(error) => resumable_failed(error),
+    }
+}
+
+fn history_failed(action: &str, error: std::io::Error) -> HttpResponse {
+    log::warn!("Failed to {}: {}", action, error);
+    match error.kind() {
+        std::io::ErrorKind::NotFound => HttpResponse::NotFound().finish(),
+        std::io::ErrorKind::AlreadyExists => {
+            HttpResponse::Conflict().body("Another file has taken its place.")
+        }
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Lists the previous versions of a file, oldest first.
+async fn list_versions(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanRead) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    match storage.resolve(&info.0) {
+        Ok(path) => HttpResponse::Ok().json(blobs.versions(&path)),
+        Err(error) => rejected_path(error),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreQuery {
+    version: u64,
+}
+
+// Makes a previous version of a file its current content,
+// the replaced content becoming a new version.
+async fn restore_version(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String,)>,
+    query: web::Query<RestoreQuery>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let filename = &info.0;
+    let path = match storage.resolve(filename) {
+        Ok(path) => path,
+        Err(error) => return rejected_path(error),
+    };
+    let Some(version) = blobs
+        .versions(&path)
+        .into_iter()
+        .find(|v| v.number == query.version)
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    let used = used_by(&user, &storage, &blobs, &uploads, None);
+    let freed = blobs.freed_by_replacing(&path);
+    if account
+        .quota_left(used, freed)
+        .is_some_and(|quota_left| version.size > quota_left)
+    {
+        return HttpResponse::InsufficientStorage().finish();
+    }
+    match blobs.restore_version(&path, version.number) {
+        Ok(hash) => {
+            log::info!(
+                "Restored version {} of file \"{}\"",
+                version.number,
+                filename
+            );
+            HttpResponse::Ok()
+                .insert_header((header::ETAG, format!("\"{}\"", hash)))
+                .finish()
+        }
+        Err(error) => history_failed("restore a version", error),
+    }
+}
+
+// Lists the deleted files which can still be restored, most recently deleted first.
+async fn list_trash(account: Account, blobs: web::Data<BlobStore>) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanRead) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    HttpResponse::Ok().json(blobs.trash(storage.root()))
+}
+
+// Puts a deleted file back where it was.
+async fn restore_deleted(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    uploads: web::Data<ResumableUploads>,
+    info: Path<(String,)>,
+) -> HttpResponse {
+    let (user, storage) = match account.authorize(DbPrivilege::CanWrite) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let id = &info.0;
+    let Some(entry) = blobs
+        .trash(storage.root())
+        .into_iter()
+        .find(|entry| entry.id == *id)
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    // The deleted file is taken out of the trash.
+    let used = used_by(&user, &storage, &blobs, &uploads, None);
+    if account
+        .quota_left(used, entry.size)
+        .is_some_and(|quota_left| entry.size > quota_left)
+    {
+        return HttpResponse::InsufficientStorage().finish();
+    }
+    match blobs.restore_deleted(storage.root(), id) {
+        Ok(hash) => {
+            log::info!("Restored deleted file \"{}\"", entry.path);
+            HttpResponse::Created()
+                .insert_header((header::ETAG, format!("\"{}\"", hash)))
+                .insert_header((header::LOCATION, location(&entry.path)))
+                .finish()
+        }
+        Err(error) => history_failed("restore a deleted file", error),
+    }
+}
+
+// Deletes permanently a file of the trash, or all of them if no id is given.
+async fn empty_trash(
+    account: Account,
+    blobs: web::Data<BlobStore>,
+    info: Option<Path<(String,)>>,
+) -> HttpResponse {
+    let (_, storage) = match account.authorize(DbPrivilege::CanDelete) {
+        Ok(authorized) => authorized,
+        Err(error) => return error.error_response(),
+    };
+    let id = info.as_ref().map(|info| info.0.as_str());
+    match blobs.empty_trash(storage.root(), id) {
+        Ok(count) => {
+            log::info!("Deleted {} files from the trash", count);
+            HttpResponse::NoContent().finish()
+        }
+        Err(error) => history_failed("empty the trash", error),
+    }
 }
 
 async fn invalid_resource(req: HttpRequest) -> impl Responder {
-    println!("Invalid URI: \"{}\"", req.uri());
+    log::warn!("Invalid URI: \"{}\"", req.uri());
     HttpResponse::NotFound()
 }
 
+// The storage is ready if files can be created in its root directory.
+fn check_storage(root: &FsPath) -> Result<(), String> {
+    let probe = root.join(".readyz");
+    std::fs::write(&probe, b"").map_err(|e| format!("cannot write to the storage: {}", e))?;
+    std::fs::remove_file(&probe).map_err(|e| format!("cannot delete from the storage: {}", e))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let server_addr = "127.0.0.1:8080";
-    println!("Listening at address {} ...", server_addr);
-    HttpServer::new(|| {
+    let config = Config::load("FILE_TRANSFER", 8080).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    server_telemetry::init_logging(&config.log.level);
+
+    let upload_settings = config
+        .section::<UploadSettings>("upload")
+        .unwrap_or_else(|e| {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        });
+    let max_chunk_size = upload_settings.max_chunk_size as usize;
+    let root = config.storage.path.clone().unwrap_or(PathBuf::from("."));
+    let storage = web::Data::new(Storage::open(&root)?);
+    let history_settings = config
+        .section::<HistorySettings>("history")
+        .unwrap_or_else(|e| {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        });
+    let purge_interval = Duration::from_secs(history_settings.purge_interval_minutes.max(1) * 60);
+    let blobs = web::Data::new(BlobStore::open(storage.root(), history_settings)?);
+    let users = config
+        .section::<AuthSettings>("auth")
+        .map_err(|e| e.to_string())
+        .and_then(|settings| Users::new(settings, &storage))
+        .unwrap_or_else(|e| {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        });
+    let users = web::Data::new(users);
+    let uploads = web::Data::new(ResumableUploads::open(
+        storage.root(),
+        upload_settings.clone(),
+    )?);
+    let namer = web::Data::new(Namer::open(storage.root(), upload_settings.naming)?);
+    let upload_settings = web::Data::new(upload_settings);
+    let telemetry = Telemetry::new({
+        let storage = storage.clone();
+        move || check_storage(storage.root())
+    });
+    // Deletes the expired files of the trash in the background.
+    actix_web::rt::spawn({
+        let blobs = blobs.clone();
+        async move {
+            let mut interval = actix_web::rt::time::interval(purge_interval);
+            loop {
+                interval.tick().await;
+                let blobs = blobs.clone();
+                match web::block(move || blobs.purge(Utc::now())).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(count)) => log::info!("Purged {} expired files from the trash", count),
+                    Ok(Err(error)) => log::error!("Failed to purge the trash: {}", error),
+                    Err(error) => log::error!("Failed to purge the trash: {}", error),
+                }
+            }
+        }
+    });
+    let allowed_origins = config.server.allowed_origins.clone();
+    log::info!(
+        "Listening at address {}:{} ...",
+        config.server.bind_address,
+        config.server.port
+    );
+    HttpServer::new(move || {
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin));
         App::new()
+            .wrap(cors)
+            .wrap(telemetry.middleware())
+            .configure(|cfg| telemetry.configure(cfg))
+            .app_data(users.clone())
+            .app_data(blobs.clone())
+            .app_data(upload_settings.clone())
+            .app_data(uploads.clone())
+            .app_data(namer.clone())
+            .service(
+                web::scope("/.uploads")
+                    .app_data(web::PayloadConfig::new(max_chunk_size))
+                    .route("", web::post().to(create_upload))
+                    .route("/{id}", web::get().to(upload_progress))
+                    .route("/{id}", web::delete().to(abort_upload))
+                    .route("/{id}/chunks/{index}", web::put().to(upload_chunk))
+                    .route("/{id}/finalize", web::post().to(finalize_upload)),
+            )
+            .service(
+                web::scope("/.versions")
+                    .route("/{path:.*}", web::get().to(list_versions))
+                    .route("/{path:.*}", web::post().to(restore_version)),
+            )
+            .service(
+                web::scope("/.trash")
+                    .route("", web::get().to(list_trash))
+                    .route("", web::delete().to(empty_trash))
+                    .route("/{id}", web::post().to(restore_deleted))
+                    .route("/{id}", web::delete().to(empty_trash)),
+            )
             .service(
-                web::resource("/{filename}")
+                web::resource("/{path:.*}")
                     .route(web::delete().to(delete_file))
                     .route(web::get().to(download_file))
+                    .route(web::head().to(download_file))
+                    .route(web::method(Method::from_bytes(b"MKCOL").unwrap()).to(make_directory))
                     .route(web::put().to(upload_specific_file))
                     .route(web::post().to(upload_new_file)),
             )
             .default_service(web::route().to(invalid_resource))
     })
-    .bind(server_addr)?
+    .bind((config.server.bind_address, config.server.port))?
     .run()
     .await
 }
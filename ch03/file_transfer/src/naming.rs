@@ -0,0 +1,94 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::blobs::STORE_DIR;
+
+const COUNTER_FILE: &str = "counter";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamingStrategy {
+    #[default]
+    Uuid,
+    Timestamp,
+    Counter,
+}
+
+// Generates the names of the files uploaded without specifying one.
+// The unique part of a name is inserted between the stem and the extension of the template.
+pub struct Namer {
+    strategy: NamingStrategy,
+    // Last value of the counter, persisted so that names are not reused after a restart.
+    counter: Mutex<u64>,
+    counter_path: PathBuf,
+}
+
+impl Namer {
+    pub fn open(root: &Path, strategy: NamingStrategy) -> io::Result<Namer> {
+        let counter_path = root.join(STORE_DIR).join(COUNTER_FILE);
+        let counter = match std::fs::read_to_string(&counter_path) {
+            Ok(contents) => contents
+                .trim()
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Namer {
+            strategy,
+            counter: Mutex::new(counter),
+            counter_path,
+        })
+    }
+
+    fn unique_part(&self, attempt: u32) -> io::Result<String> {
+        Ok(match self.strategy {
+            NamingStrategy::Uuid => Uuid::new_v4().to_string(),
+            NamingStrategy::Timestamp => {
+                let timestamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+                if attempt == 0 {
+                    timestamp
+                } else {
+                    format!("{}-{}", timestamp, attempt)
+                }
+            }
+            NamingStrategy::Counter => {
+                let mut counter = self.counter.lock().unwrap();
+                *counter += 1;
+                std::fs::write(&self.counter_path, counter.to_string())?;
+                format!("{:06}", *counter)
+            }
+        })
+    }
+
+    // Returns a candidate name for the `attempt`-th try of storing a file
+    // described by `template`, which is a directory ending with a slash,
+    // or a file name whose stem and extension are kept.
+    pub fn name(&self, template: &str, attempt: u32) -> io::Result<String> {
+        let unique = self.unique_part(attempt)?;
+        let (dir, file_name) = match template.rfind('/') {
+            Some(n) => template.split_at(n + 1),
+            None => ("", template),
+        };
+        // A leading dot denotes a hidden file rather than an extension,
+        // and compressed archives keep both their extensions.
+        let (mut stem, mut extension) = match file_name.rfind('.') {
+            Some(n) if n > 0 => (&file_name[..n], &file_name[n..]),
+            _ => (file_name, ""),
+        };
+        if stem.len() > 4 && stem.ends_with(".tar") {
+            extension = &file_name[stem.len() - 4..];
+            stem = &stem[..stem.len() - 4];
+        }
+        Ok(if stem.is_empty() {
+            format!("{}{}{}", dir, unique, extension)
+        } else {
+            format!("{}{}-{}{}", dir, stem, unique, extension)
+        })
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::blobs::{self, STORE_DIR};
+use crate::upload::{PartialFile, UploadSettings};
+
+const SESSION_FILE: &str = "session.json";
+const DATA_FILE: &str = "data";
+
+#[derive(Debug, Deserialize)]
+pub struct NewSession {
+    pub path: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    // Hash of the whole file, checked when the upload is finalized.
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    // The user who started the upload, and the only one who can see it.
+    pub owner: String,
+    pub path: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub sha256: Option<String>,
+    pub received: BTreeSet<u64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub fn chunk_count(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size)
+    }
+
+    fn chunk_length(&self, index: u64) -> u64 {
+        (self.size - index * self.chunk_size).min(self.chunk_size)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Progress {
+    #[serde(flatten)]
+    pub session: Session,
+    pub chunk_count: u64,
+    pub received_bytes: u64,
+    pub complete: bool,
+}
+
+impl From<Session> for Progress {
+    fn from(session: Session) -> Progress {
+        let received_bytes = session
+            .received
+            .iter()
+            .map(|&n| session.chunk_length(n))
+            .sum();
+        Progress {
+            chunk_count: session.chunk_count(),
+            received_bytes,
+            complete: session.received.len() as u64 == session.chunk_count(),
+            session,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResumableError {
+    NotFound,
+    Invalid(String),
+    TooLarge(u64),
+    QuotaExceeded,
+    ChecksumMismatch,
+    Incomplete(u64),
+    Io(io::Error),
+}
+
+impl fmt::Display for ResumableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResumableError::NotFound => write!(f, "no such upload session"),
+            ResumableError::Invalid(msg) => write!(f, "{}", msg),
+            ResumableError::TooLarge(max_size) => {
+                write!(f, "the file is larger than {} bytes", max_size)
+            }
+            ResumableError::QuotaExceeded => write!(f, "the storage quota is exceeded"),
+            ResumableError::ChecksumMismatch => write!(f, "the checksum does not match"),
+            ResumableError::Incomplete(missing) => write!(f, "{} chunks are missing", missing),
+            ResumableError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ResumableError {
+    fn from(e: io::Error) -> ResumableError {
+        ResumableError::Io(e)
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Uploads of files sent in numbered chunks, possibly over several connections.
+// Each session is kept in its own directory, with the chunks written at their place
+// in a data file, so that the uploads can be resumed after a restart of the server.
+pub struct ResumableUploads {
+    dir: PathBuf,
+    settings: UploadSettings,
+    sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>,
+}
+
+impl ResumableUploads {
+    pub fn open(root: &Path, settings: UploadSettings) -> io::Result<ResumableUploads> {
+        let dir = root.join(STORE_DIR).join("uploads");
+        std::fs::create_dir_all(&dir)?;
+        let mut sessions = HashMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let session_dir = entry?.path();
+            match std::fs::read(session_dir.join(SESSION_FILE))
+                .map_err(|e| e.to_string())
+                .and_then(|s| serde_json::from_slice::<Session>(&s).map_err(|e| e.to_string()))
+            {
+                Ok(session) => {
+                    sessions.insert(session.id.clone(), Arc::new(Mutex::new(session)));
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Discarding the upload session \"{}\": {}",
+                        session_dir.display(),
+                        e
+                    );
+                    let _ = std::fs::remove_dir_all(&session_dir);
+                }
+            }
+        }
+        let uploads = ResumableUploads {
+            dir,
+            settings,
+            sessions: Mutex::new(sessions),
+        };
+        uploads.purge_expired();
+        Ok(uploads)
+    }
+
+    fn session_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn save(&self, session: &Session) -> io::Result<()> {
+        let dir = self.session_dir(&session.id);
+        let temp_path = dir.join(format!("{}.tmp", SESSION_FILE));
+        std::fs::write(&temp_path, serde_json::to_vec(session)?)?;
+        std::fs::rename(temp_path, dir.join(SESSION_FILE))
+    }
+
+    fn get(&self, id: &str, owner: &str) -> Result<Arc<Mutex<Session>>, ResumableError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|session| session.lock().unwrap().owner == owner)
+            .cloned()
+            .ok_or(ResumableError::NotFound)
+    }
+
+    // Deletes the sessions not finalized in time.
+    fn purge_expired(&self) {
+        let expiry = Utc::now() - Duration::hours(self.settings.session_expiry_hours as i64);
+        self.sessions.lock().unwrap().retain(|id, session| {
+            let expired = session.lock().unwrap().created_at < expiry;
+            if expired {
+                log::info!("Upload session {} expired", id);
+                let _ = std::fs::remove_dir_all(self.dir.join(id));
+            }
+            !expired
+        });
+    }
+
+    // The space taken by the uploads of `owner`, except `excluded`,
+    // as the data file of each one is created with the size of the whole file.
+    pub fn reserved(&self, owner: &str, excluded: Option<&str>) -> u64 {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| Some(id.as_str()) != excluded)
+            .map(|(_, session)| session.lock().unwrap())
+            .filter(|session| session.owner == owner)
+            .map(|session| session.size)
+            .sum()
+    }
+
+    // `path` must have been checked by the caller.
+    // The quota left must account for the other uploads of `owner`.
+    pub fn create(
+        &self,
+        owner: &str,
+        new_session: NewSession,
+        quota_left: Option<u64>,
+    ) -> Result<Session, ResumableError> {
+        if new_session.size > self.settings.max_size {
+            return Err(ResumableError::TooLarge(self.settings.max_size));
+        }
+        if quota_left.is_some_and(|quota_left| new_session.size > quota_left) {
+            return Err(ResumableError::QuotaExceeded);
+        }
+        if new_session.chunk_size == 0 || new_session.chunk_size > self.settings.max_chunk_size {
+            return Err(ResumableError::Invalid(format!(
+                "the chunk size must be between 1 and {} bytes",
+                self.settings.max_chunk_size
+            )));
+        }
+        self.purge_expired();
+
+        let session = Session {
+            id: format!("{:032x}", rand::thread_rng().gen::<u128>()),
+            owner: owner.to_string(),
+            path: new_session.path,
+            size: new_session.size,
+            chunk_size: new_session.chunk_size,
+            sha256: new_session.sha256.map(|h| h.to_lowercase()),
+            received: BTreeSet::new(),
+            created_at: Utc::now(),
+        };
+        let dir = self.session_dir(&session.id);
+        std::fs::create_dir(&dir)?;
+        std::fs::File::create(dir.join(DATA_FILE))?.set_len(session.size)?;
+        self.save(&session)?;
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.id.clone(), Arc::new(Mutex::new(session.clone())));
+        Ok(session)
+    }
+
+    pub fn progress(&self, id: &str, owner: &str) -> Result<Progress, ResumableError> {
+        Ok(self.get(id, owner)?.lock().unwrap().clone().into())
+    }
+
+    // Writes the chunk numbered `index`, after checking it against its hash.
+    // A chunk can be sent again, for instance if its acknowledgment was lost.
+    pub fn write_chunk(
+        &self,
+        id: &str,
+        owner: &str,
+        index: u64,
+        sha256: &str,
+        data: &[u8],
+    ) -> Result<Progress, ResumableError> {
+        let session = self.get(id, owner)?;
+        let (offset, length) = {
+            let session = session.lock().unwrap();
+            if index >= session.chunk_count() {
+                return Err(ResumableError::Invalid(format!(
+                    "there are only {} chunks",
+                    session.chunk_count()
+                )));
+            }
+            (index * session.chunk_size, session.chunk_length(index))
+        };
+        if data.len() as u64 != length {
+            return Err(ResumableError::Invalid(format!(
+                "chunk {} must be {} bytes long",
+                index, length
+            )));
+        }
+        if !sha256.eq_ignore_ascii_case(&sha256_hex(data)) {
+            return Err(ResumableError::ChecksumMismatch);
+        }
+
+        // Chunks of the same session can be written concurrently, at different places.
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.session_dir(id).join(DATA_FILE))?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_data()?;
+
+        let mut session = session.lock().unwrap();
+        session.received.insert(index);
+        self.save(&session)?;
+        Ok(session.clone().into())
+    }
+
+    // Checks that the upload is complete, and hands over its data to `store`,
+    // which moves it to its destination. The session is then deleted.
+    pub fn finalize<T>(
+        &self,
+        id: &str,
+        owner: &str,
+        store: impl FnOnce(&PartialFile) -> io::Result<T>,
+    ) -> Result<T, ResumableError> {
+        let session = self.get(id, owner)?;
+        let result = {
+            let session = session.lock().unwrap();
+            let missing = session.chunk_count() - session.received.len() as u64;
+            if missing > 0 {
+                return Err(ResumableError::Incomplete(missing));
+            }
+            let data_path = self.session_dir(id).join(DATA_FILE);
+            let sha256 = blobs::sha256_of_file(&data_path)?;
+            if session.sha256.as_ref().is_some_and(|h| *h != sha256) {
+                return Err(ResumableError::ChecksumMismatch);
+            }
+            store(&PartialFile::new(data_path, sha256))?
+        };
+        self.remove(id, owner)?;
+        Ok(result)
+    }
+
+    pub fn remove(&self, id: &str, owner: &str) -> Result<(), ResumableError> {
+        self.get(id, owner)?;
+        self.sessions
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or(ResumableError::NotFound)?;
+        std::fs::remove_dir_all(self.session_dir(id))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A scratch directory holding the storage root of a test.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Scratch {
+            Scratch(std::env::temp_dir().join(format!("file_transfer-{}", uuid::Uuid::new_v4())))
+        }
+
+        fn uploads(&self) -> ResumableUploads {
+            let settings = UploadSettings {
+                max_size: 100,
+                max_chunk_size: 4,
+                session_expiry_hours: 24,
+                ..UploadSettings::default()
+            };
+            ResumableUploads::open(&self.0, settings).unwrap()
+        }
+
+        fn session_dir(&self, id: &str) -> PathBuf {
+            self.0.join(STORE_DIR).join("uploads").join(id)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const CONTENTS: &[u8] = b"hello, world";
+
+    fn create(uploads: &ResumableUploads, sha256: Option<String>) -> Session {
+        let new_session = NewSession {
+            path: "a.txt".to_string(),
+            size: CONTENTS.len() as u64,
+            chunk_size: 4,
+            sha256,
+        };
+        uploads.create("susan", new_session, None).unwrap()
+    }
+
+    fn write_chunk(uploads: &ResumableUploads, id: &str, index: u64) -> Progress {
+        let chunk = &CONTENTS[index as usize * 4..(index as usize + 1) * 4];
+        uploads
+            .write_chunk(id, "susan", index, &sha256_hex(chunk), chunk)
+            .unwrap()
+    }
+
+    #[test]
+    fn chunks_are_checked_against_their_length_and_hash() {
+        let scratch = Scratch::new();
+        let uploads = scratch.uploads();
+        let id = create(&uploads, None).id;
+
+        let result = uploads.write_chunk(&id, "susan", 0, &sha256_hex(b"hel"), b"hel");
+        assert!(matches!(result, Err(ResumableError::Invalid(_))));
+        let result = uploads.write_chunk(&id, "susan", 3, &sha256_hex(b"!"), b"!");
+        assert!(matches!(result, Err(ResumableError::Invalid(_))));
+        let result = uploads.write_chunk(&id, "susan", 0, &sha256_hex(b"HELL"), b"hell");
+        assert!(matches!(result, Err(ResumableError::ChecksumMismatch)));
+        let result = uploads.write_chunk(&id, "joe", 0, &sha256_hex(b"hell"), b"hell");
+        assert!(matches!(result, Err(ResumableError::NotFound)));
+
+        let progress = write_chunk(&uploads, &id, 0);
+        assert_eq!(progress.received_bytes, 4);
+        assert!(!progress.complete);
+    }
+
+    #[test]
+    fn sessions_are_reloaded_after_a_restart() {
+        let scratch = Scratch::new();
+        let uploads = scratch.uploads();
+        let id = create(&uploads, Some(sha256_hex(CONTENTS))).id;
+        write_chunk(&uploads, &id, 2);
+        write_chunk(&uploads, &id, 0);
+        drop(uploads);
+
+        let uploads = scratch.uploads();
+        let progress = uploads.progress(&id, "susan").unwrap();
+        assert_eq!(progress.session.received, BTreeSet::from([0, 2]));
+        assert_eq!(uploads.reserved("susan", None), CONTENTS.len() as u64);
+        let result = uploads.finalize(&id, "susan", |_| Ok(()));
+        assert!(matches!(result, Err(ResumableError::Incomplete(1))));
+
+        assert!(write_chunk(&uploads, &id, 1).complete);
+        let contents = uploads
+            .finalize(&id, "susan", |file| std::fs::read(file.path()))
+            .unwrap();
+        assert_eq!(contents, CONTENTS);
+        assert!(!scratch.session_dir(&id).exists());
+        assert_eq!(uploads.reserved("susan", None), 0);
+    }
+
+    #[test]
+    fn unreadable_and_expired_sessions_are_deleted_on_open() {
+        let scratch = Scratch::new();
+        let uploads = scratch.uploads();
+        let expired = create(&uploads, None);
+        let kept = create(&uploads, None).id;
+        let corrupt = create(&uploads, None).id;
+        drop(uploads);
+        let session_file = scratch.session_dir(&expired.id).join(SESSION_FILE);
+        let old = Session {
+            created_at: Utc::now() - Duration::hours(25),
+            ..expired.clone()
+        };
+        std::fs::write(session_file, serde_json::to_vec(&old).unwrap()).unwrap();
+        std::fs::write(scratch.session_dir(&corrupt).join(SESSION_FILE), b"{").unwrap();
+
+        let uploads = scratch.uploads();
+        assert!(uploads.progress(&kept, "susan").is_ok());
+        for id in [&expired.id, &corrupt] {
+            assert!(matches!(
+                uploads.progress(id, "susan"),
+                Err(ResumableError::NotFound)
+            ));
+            assert!(!scratch.session_dir(id).exists());
+        }
+    }
+
+    #[test]
+    fn finalize_rejects_a_checksum_mismatch() {
+        let scratch = Scratch::new();
+        let uploads = scratch.uploads();
+        let id = create(&uploads, Some(sha256_hex(b"something else"))).id;
+        for index in 0..3 {
+            write_chunk(&uploads, &id, index);
+        }
+        let result = uploads.finalize(&id, "susan", |_| Ok(()));
+        assert!(matches!(result, Err(ResumableError::ChecksumMismatch)));
+        // The session is kept, for the client to find out what went wrong.
+        assert!(uploads.progress(&id, "susan").unwrap().complete);
+    }
+
+    #[test]
+    fn create_checks_the_sizes() {
+        let scratch = Scratch::new();
+        let uploads = scratch.uploads();
+        let new_session = |size, chunk_size| NewSession {
+            path: "a.txt".to_string(),
+            size,
+            chunk_size,
+            sha256: None,
+        };
+        let result = uploads.create("susan", new_session(101, 4), None);
+        assert!(matches!(result, Err(ResumableError::TooLarge(100))));
+        let result = uploads.create("susan", new_session(10, 5), None);
+        assert!(matches!(result, Err(ResumableError::Invalid(_))));
+        let result = uploads.create("susan", new_session(10, 4), Some(9));
+        assert!(matches!(result, Err(ResumableError::QuotaExceeded)));
+    }
+}
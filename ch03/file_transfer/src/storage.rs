@@ -0,0 +1,224 @@
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug)]
+pub enum StorageError {
+    // The path is not a plain relative path.
+    InvalidPath(String),
+    // The path leads outside of the storage root through a symbolic link.
+    OutsideRoot(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::InvalidPath(path) => write!(f, "invalid path \"{}\"", path),
+            StorageError::OutsideRoot(path) => {
+                write!(f, "path \"{}\" leads outside of the storage", path)
+            }
+            StorageError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> StorageError {
+        StorageError::Io(e)
+    }
+}
+
+// The directory where all the files are stored.
+// The paths received from clients are resolved only through it.
+pub struct Storage {
+    root: PathBuf,
+}
+
+impl Storage {
+    // Creates the root directory if it does not exist yet.
+    pub fn open(root: &Path) -> io::Result<Storage> {
+        std::fs::create_dir_all(root)?;
+        Ok(Storage {
+            root: root.canonicalize()?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Resolves a path relative to the root, rejecting the absolute paths,
+    // the `..` components, and the paths traversing symbolic links pointing outside of the root.
+    // The names starting with a dot are reserved for the server's own files.
+    pub fn resolve(&self, relative: &str) -> Result<PathBuf, StorageError> {
+        let invalid = || StorageError::InvalidPath(relative.to_string());
+        // Backslashes are separators on Windows, so they could smuggle in `..` components.
+        if relative.is_empty() || relative.contains(['\0', '\\']) {
+            return Err(invalid());
+        }
+        let mut path = self.root.clone();
+        for component in Path::new(relative).components() {
+            match component {
+                Component::Normal(name) if name.to_string_lossy().starts_with('.') => {
+                    return Err(invalid())
+                }
+                Component::Normal(name) => path.push(name),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(invalid())
+                }
+            }
+        }
+        if path == self.root {
+            return Err(invalid());
+        }
+
+        // The nearest existing ancestor determines where the path actually leads,
+        // including for dangling links that would be followed when creating a file.
+        let existing = path
+            .ancestors()
+            .find(|p| p.symlink_metadata().is_ok())
+            .unwrap_or(&self.root);
+        match existing.canonicalize() {
+            Ok(real) if real.starts_with(&self.root) => Ok(path),
+            Ok(_) => Err(StorageError::OutsideRoot(relative.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(StorageError::OutsideRoot(relative.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    // A scratch directory, containing the storage root and what lies outside of it.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new() -> Scratch {
+            let dir = std::env::temp_dir().join(format!("file_transfer-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(dir.join("outside")).unwrap();
+            Scratch(dir)
+        }
+
+        fn storage(&self) -> Storage {
+            Storage::open(&self.0.join("root")).unwrap()
+        }
+
+        fn outside(&self) -> PathBuf {
+            self.0.join("outside").canonicalize().unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn assert_invalid(storage: &Storage, relative: &str) {
+        assert!(
+            matches!(storage.resolve(relative), Err(StorageError::InvalidPath(_))),
+            "{:?} should be invalid",
+            relative
+        );
+    }
+
+    fn assert_outside(storage: &Storage, relative: &str) {
+        assert!(
+            matches!(storage.resolve(relative), Err(StorageError::OutsideRoot(_))),
+            "{:?} should lead outside",
+            relative
+        );
+    }
+
+    #[test]
+    fn resolves_plain_paths_under_the_root() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        assert_eq!(
+            storage.resolve("a.txt").unwrap(),
+            storage.root().join("a.txt")
+        );
+        assert_eq!(
+            storage.resolve("./dir/b.txt").unwrap(),
+            storage.root().join("dir").join("b.txt")
+        );
+    }
+
+    #[test]
+    fn rejects_traversals() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        for relative in ["..", "../x", "a/../../x", "a/..", "/etc/passwd", "//x"] {
+            assert_invalid(&storage, relative);
+        }
+    }
+
+    #[test]
+    fn rejects_backslashes_and_nul() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        for relative in ["..\\x", "a\\..\\..\\x", "a\\b", "a\0b", "\0"] {
+            assert_invalid(&storage, relative);
+        }
+    }
+
+    #[test]
+    fn rejects_dot_prefixed_names() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        for relative in [".store", ".store/blobs", "a/.hidden", ".a.txt.part"] {
+            assert_invalid(&storage, relative);
+        }
+    }
+
+    #[test]
+    fn rejects_the_root_itself() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        for relative in ["", ".", "./", "./."] {
+            assert_invalid(&storage, relative);
+        }
+    }
+
+    #[test]
+    fn rejects_links_to_directories_outside() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        symlink(scratch.outside(), storage.root().join("out")).unwrap();
+        assert_outside(&storage, "out");
+        assert_outside(&storage, "out/new.txt");
+        assert_outside(&storage, "out/dir/new.txt");
+    }
+
+    #[test]
+    fn rejects_dangling_links_to_outside() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        symlink(
+            scratch.outside().join("missing.txt"),
+            storage.root().join("dangling.txt"),
+        )
+        .unwrap();
+        assert_outside(&storage, "dangling.txt");
+    }
+
+    #[test]
+    fn follows_links_inside_the_root() {
+        let scratch = Scratch::new();
+        let storage = scratch.storage();
+        std::fs::create_dir(storage.root().join("dir")).unwrap();
+        symlink(storage.root().join("dir"), storage.root().join("alias")).unwrap();
+        assert_eq!(
+            storage.resolve("alias/a.txt").unwrap(),
+            storage.root().join("alias").join("a.txt")
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use actix_web::{web, HttpRequest};
+use futures::StreamExt;
+use rand::Rng;
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::naming::NamingStrategy;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadSettings {
+    // Maximum size in bytes of an uploaded file.
+    pub max_size: u64,
+    // Maximum size in bytes of a chunk of a resumable upload.
+    pub max_chunk_size: u64,
+    // Time after which an unfinished resumable upload is deleted.
+    pub session_expiry_hours: u64,
+    // How the names of the files uploaded without one are generated.
+    pub naming: NamingStrategy,
+}
+
+impl Default for UploadSettings {
+    fn default() -> UploadSettings {
+        UploadSettings {
+            max_size: 100 * 1024 * 1024,
+            max_chunk_size: 8 * 1024 * 1024,
+            session_expiry_hours: 24 * 7,
+            naming: NamingStrategy::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    TooLarge(u64),
+    QuotaExceeded,
+    Payload(actix_web::error::PayloadError),
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UploadError::TooLarge(max_size) => {
+                write!(f, "the file is larger than {} bytes", max_size)
+            }
+            UploadError::QuotaExceeded => write!(f, "the storage quota is exceeded"),
+            UploadError::Payload(e) => write!(f, "{}", e),
+            UploadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> UploadError {
+        UploadError::Io(e)
+    }
+}
+
+// A received file, kept out of the clients' sight until it is stored.
+// It is deleted when dropped.
+pub struct PartialFile {
+    path: PathBuf,
+    sha256: String,
+}
+
+impl PartialFile {
+    pub fn new(path: PathBuf, sha256: String) -> PartialFile {
+        PartialFile { path, sha256 }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Streams the request body to a new file in `temp_dir`, computing its hash on the way.
+// The size of the file is limited by the settings and by what is left of the user's quota.
+pub async fn receive(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+    temp_dir: &Path,
+    settings: &UploadSettings,
+    quota_left: Option<u64>,
+) -> Result<PartialFile, UploadError> {
+    let check_size = |size: u64| {
+        if size > settings.max_size {
+            Err(UploadError::TooLarge(settings.max_size))
+        } else if quota_left.is_some_and(|quota_left| size > quota_left) {
+            Err(UploadError::QuotaExceeded)
+        } else {
+            Ok(())
+        }
+    };
+
+    // Reject early the bodies announced as too large.
+    let announced_length = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let Some(length) = announced_length {
+        check_size(length)?;
+    }
+
+    let mut partial = PartialFile::new(
+        temp_dir.join(format!("{:016x}.part", rand::thread_rng().gen::<u64>())),
+        String::new(),
+    );
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial.path)
+        .await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(UploadError::Payload)?;
+        size += chunk.len() as u64;
+        check_size(size)?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    partial.sha256 = format!("{:x}", hasher.finalize());
+    Ok(partial)
+}
@@ -0,0 +1,405 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::blocking::{Body, RequestBuilder, Response};
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Method, StatusCode, Url};
+use serde_derive::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::progress::Progress;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+// Only the connection is timed out, as transferring a large file can take long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+// Delay before the first retry, doubled at each retry.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+// Maximum length of the error messages read from the responses.
+const MAX_MESSAGE_LENGTH: u64 = 4096;
+
+#[derive(Debug)]
+pub enum ClientError {
+    // The server cannot be reached, so the request was not processed.
+    Connect(reqwest::Error),
+    // The request failed after reaching the server.
+    Http(reqwest::Error),
+    // The connection failed while receiving a file.
+    Io(io::Error),
+    // A local file cannot be read or written.
+    Local(PathBuf, io::Error),
+    // The server answered with an unexpected status.
+    Status(u16, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Connect(e) => write!(f, "cannot connect to the server: {}", e),
+            ClientError::Http(e) => write!(f, "{}", e),
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::Local(path, e) => write!(f, "{}: {}", path.display(), e),
+            ClientError::Status(status, msg) if msg.is_empty() => {
+                write!(f, "the server answered with status {}", status)
+            }
+            ClientError::Status(status, msg) => {
+                write!(f, "the server answered with status {}: {}", status, msg)
+            }
+            ClientError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> ClientError {
+        if e.is_connect() {
+            ClientError::Connect(e)
+        } else {
+            ClientError::Http(e)
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+impl ClientError {
+    // Whether the request can succeed if it is sent again.
+    // 507 means that the quota is exceeded, which will not change by itself.
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Connect(_) | ClientError::Io(_) => true,
+            ClientError::Http(e) => !e.is_builder() && !e.is_redirect(),
+            ClientError::Status(status, _) => *status >= 500 && *status != 507,
+            ClientError::Local(..) | ClientError::Invalid(_) => false,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ClientError::Status(404, _))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub entry_type: EntryType,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+// The hash of the contents of a file, from its entity tag.
+fn sha256_in(headers: &HeaderMap) -> Option<String> {
+    let etag = headers.get(header::ETAG)?.to_str().ok()?.trim_matches('"');
+    (etag.len() == 64 && etag.bytes().all(|b| b.is_ascii_hexdigit())).then(|| etag.to_lowercase())
+}
+
+// Turns the unexpected statuses into errors carrying the message of the server.
+fn expect(response: Response, statuses: &[StatusCode]) -> Result<Response, ClientError> {
+    if statuses.contains(&response.status()) {
+        return Ok(response);
+    }
+    let status = response.status().as_u16();
+    let mut msg = String::new();
+    let _ = response.take(MAX_MESSAGE_LENGTH).read_to_string(&mut msg);
+    Err(ClientError::Status(status, msg.trim().to_string()))
+}
+
+// The body of an upload, read from a local file,
+// counting the bytes sent so that they can be taken back if the upload fails.
+struct Upload {
+    file: File,
+    sent: Arc<AtomicU64>,
+    progress: Progress,
+}
+
+impl Read for Upload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.sent.fetch_add(n as u64, Ordering::Relaxed);
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+}
+
+pub fn sha256_of_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// A blocking client of the file_transfer API.
+pub struct Client {
+    http: reqwest::blocking::Client,
+    // Address of the API, which can be under a path of the server.
+    base_url: Url,
+    credentials: Option<(String, String)>,
+    retries: u32,
+}
+
+impl Client {
+    // `url` is like "http://127.0.0.1:8080", and `credentials` like "name:password".
+    pub fn new(url: &str, credentials: Option<&str>, retries: u32) -> Result<Client, ClientError> {
+        let base_url = Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https") && !url.cannot_be_a_base())
+            .ok_or_else(|| ClientError::Invalid(format!("unsupported URL \"{}\"", url)))?;
+        let credentials = credentials.map(|credentials| {
+            let (username, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+            (username.to_string(), password.to_string())
+        });
+        let http = reqwest::blocking::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(None)
+            .build()?;
+        Ok(Client {
+            http,
+            base_url,
+            credentials,
+            retries,
+        })
+    }
+
+    fn request(&self, method: Method, remote: &str) -> RequestBuilder {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("the URL is checked when creating the client")
+            .pop_if_empty()
+            .extend(remote.trim_start_matches('/').split('/'));
+        let builder = self.http.request(method, url);
+        match &self.credentials {
+            Some((username, password)) => builder.basic_auth(username, Some(password)),
+            None => builder,
+        }
+    }
+
+    // Runs `attempt` until it succeeds, or fails with an error which is not transient,
+    // or has been retried `self.retries` times, waiting longer after each failure.
+    fn with_retries<T>(
+        &self,
+        progress: &Progress,
+        remote: &str,
+        idempotent: bool,
+        mut attempt: impl FnMut() -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut delay = RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            match attempt() {
+                // A request which is not idempotent is retried only if it has not reached the server.
+                Err(e)
+                    if retries < self.retries
+                        && e.is_transient()
+                        && (idempotent || matches!(e, ClientError::Connect(_))) =>
+                {
+                    retries += 1;
+                    progress.eprintln(&format!(
+                        "\"{}\": {}, retrying in {} ms ({}/{})",
+                        remote,
+                        e,
+                        delay.as_millis(),
+                        retries,
+                        self.retries
+                    ));
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn upload(
+        &self,
+        method: Method,
+        local: &Path,
+        remote: &str,
+        progress: &Progress,
+    ) -> Result<Response, ClientError> {
+        let local_error = |e| ClientError::Local(local.to_path_buf(), e);
+        let file = File::open(local).map_err(local_error)?;
+        let length = file.metadata().map_err(local_error)?.len();
+        let sent = Arc::new(AtomicU64::new(0));
+        let body = Upload {
+            file,
+            sent: sent.clone(),
+            progress: progress.clone(),
+        };
+        let result = self
+            .request(method, remote)
+            .body(Body::sized(body, length))
+            .send()
+            .map_err(ClientError::from)
+            .and_then(|response| expect(response, &[StatusCode::OK, StatusCode::CREATED]));
+        if result.is_err() {
+            progress.rewind(sent.load(Ordering::Relaxed));
+        }
+        result
+    }
+
+    // Stores a local file at `remote`, replacing the existing file.
+    pub fn put(&self, local: &Path, remote: &str, progress: &Progress) -> Result<(), ClientError> {
+        self.with_retries(progress, remote, true, || {
+            self.upload(Method::PUT, local, remote, progress)
+                .map(|_| ())
+        })
+    }
+
+    // Stores a local file under a name chosen by the server from `template`,
+    // which is a directory ending with a slash or a file name, and returns that name.
+    pub fn post(
+        &self,
+        local: &Path,
+        template: &str,
+        progress: &Progress,
+    ) -> Result<String, ClientError> {
+        self.with_retries(progress, template, false, || {
+            Ok(self
+                .upload(Method::POST, local, template, progress)?
+                .text()?)
+        })
+    }
+
+    // Downloads the file at `remote` to `local`, resuming the download after a failure
+    // if the file has not changed, and checks its contents against their hash.
+    pub fn get(&self, remote: &str, local: &Path, progress: &Progress) -> Result<(), ClientError> {
+        let file_name = local
+            .file_name()
+            .ok_or_else(|| ClientError::Invalid(format!("invalid path {}", local.display())))?;
+        let part_path = local.with_file_name(format!(".{}.part", file_name.to_string_lossy()));
+        let local_error = |e| ClientError::Local(part_path.clone(), e);
+        let mut received = 0;
+        let mut sha256 = None;
+        let result = self.with_retries(progress, remote, true, || {
+            let mut request = self.request(Method::GET, remote);
+            if received > 0 && sha256.is_some() {
+                request = request.header(header::RANGE, format!("bytes={}-", received));
+            }
+            let mut response = expect(
+                request.send()?,
+                &[StatusCode::OK, StatusCode::PARTIAL_CONTENT],
+            )?;
+            if !response.headers().contains_key(header::ETAG) {
+                return Err(ClientError::Invalid(format!(
+                    "\"{}\" is a directory",
+                    remote
+                )));
+            }
+            let resumed = response.status() == StatusCode::PARTIAL_CONTENT
+                && sha256.is_some()
+                && sha256_in(response.headers()) == sha256;
+            if !resumed {
+                progress.rewind(received);
+                received = 0;
+            }
+            sha256 = sha256_in(response.headers());
+            let mut file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&part_path)
+                .map_err(local_error)?;
+            file.set_len(received).map_err(local_error)?;
+            file.seek(SeekFrom::Start(received)).map_err(local_error)?;
+            let mut buffer = vec![0; BUFFER_SIZE];
+            loop {
+                let n = response.read(&mut buffer)?;
+                if n == 0 {
+                    break;
+                }
+                file.write_all(&buffer[..n]).map_err(local_error)?;
+                received += n as u64;
+                progress.add(n as u64);
+            }
+            file.sync_all().map_err(local_error)?;
+            Ok(())
+        });
+        let result = result.and_then(|_| match &sha256 {
+            Some(expected) if *expected != sha256_of_file(&part_path).map_err(local_error)? => Err(
+                ClientError::Invalid(format!("\"{}\" does not match its hash", remote)),
+            ),
+            _ => std::fs::rename(&part_path, local)
+                .map_err(|e| ClientError::Local(local.to_path_buf(), e)),
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&part_path);
+        }
+        result
+    }
+
+    pub fn list(&self, remote_dir: &str) -> Result<Vec<Entry>, ClientError> {
+        self.with_retries(&Progress::hidden(), remote_dir, true, || {
+            let response = expect(
+                self.request(Method::GET, remote_dir).send()?,
+                &[StatusCode::OK],
+            )?;
+            // Only the files have an entity tag.
+            if response.headers().contains_key(header::ETAG) {
+                return Err(ClientError::Invalid(format!(
+                    "\"{}\" is not a directory",
+                    remote_dir
+                )));
+            }
+            serde_json::from_reader(response)
+                .map_err(|e| ClientError::Invalid(format!("invalid listing: {}", e)))
+        })
+    }
+
+    // The hash of the contents of the file at `remote`, if known by the server.
+    pub fn sha256(&self, remote: &str) -> Result<Option<String>, ClientError> {
+        self.with_retries(&Progress::hidden(), remote, true, || {
+            let response = expect(
+                self.request(Method::HEAD, remote).send()?,
+                &[StatusCode::OK],
+            )?;
+            Ok(sha256_in(response.headers()))
+        })
+    }
+
+    // Creates a directory, and returns false if it already exists.
+    pub fn mkdir(&self, remote: &str) -> Result<bool, ClientError> {
+        let mkcol = Method::from_bytes(b"MKCOL").unwrap();
+        self.with_retries(&Progress::hidden(), remote, true, || {
+            let response = expect(
+                self.request(mkcol.clone(), remote).send()?,
+                &[StatusCode::CREATED, StatusCode::METHOD_NOT_ALLOWED],
+            )?;
+            Ok(response.status() == StatusCode::CREATED)
+        })
+    }
+
+    pub fn delete(&self, remote: &str, recursive: bool) -> Result<(), ClientError> {
+        self.with_retries(&Progress::hidden(), remote, true, || {
+            let mut request = self.request(Method::DELETE, remote);
+            if recursive {
+                request = request.query(&[("recursive", true)]);
+            }
+            expect(request.send()?, &[StatusCode::OK]).map(|_| ())
+        })
+    }
+}
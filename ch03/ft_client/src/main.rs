@@ -0,0 +1,374 @@
+mod client;
+mod progress;
+mod sync;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use client::{Client, ClientError, EntryType};
+use progress::Progress;
+use sync::SyncOptions;
+
+const USAGE: &str = "Usage: ft_client [OPTIONS] COMMAND [ARGS]
+
+Commands:
+  put LOCAL_FILE... [REMOTE]   Uploads files, into a directory if there are several of them
+  get REMOTE_FILE... [LOCAL]   Downloads files, into a directory if there are several of them
+  post LOCAL_FILE [REMOTE]     Uploads a file under a name generated from REMOTE,
+                               a directory ending with a slash or a file name
+  rm [-r] REMOTE...            Deletes files, or directories with their contents with -r
+  ls [REMOTE_DIR]              Lists a directory
+  sync [--checksum] [--delete] [--dry-run] LOCAL_DIR [REMOTE_DIR]
+                               Mirrors a local directory to the server, comparing the files
+                               by size and modification time, or by hash with --checksum,
+                               and deleting the remote files missing locally with --delete
+
+Options:
+  -s, --server URL           Address of the server [env: FT_SERVER] [default: http://127.0.0.1:8080]
+  -u, --user NAME:PASSWORD   Credentials [env: FT_USER]
+  -j, --jobs N               Number of parallel transfers [default: 4]
+      --retries N            Number of retries of a failed request [default: 3]";
+
+struct Options {
+    server: String,
+    credentials: Option<String>,
+    jobs: usize,
+    retries: u32,
+    recursive: bool,
+    sync: SyncOptions,
+    command: String,
+    args: Vec<String>,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("ft_client: {}", message);
+    std::process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        server: std::env::var("FT_SERVER").unwrap_or("http://127.0.0.1:8080".to_string()),
+        credentials: std::env::var("FT_USER").ok(),
+        jobs: 4,
+        retries: 3,
+        recursive: false,
+        sync: SyncOptions::default(),
+        command: String::new(),
+        args: vec![],
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("missing value of {}", name)))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "-s" | "--server" => options.server = value(&arg),
+            "-u" | "--user" => options.credentials = Some(value(&arg)),
+            "-j" | "--jobs" => {
+                options.jobs = value(&arg)
+                    .parse()
+                    .ok()
+                    .filter(|&jobs| jobs > 0)
+                    .unwrap_or_else(|| fail("the number of jobs must be a positive integer"))
+            }
+            "--retries" => {
+                options.retries = value(&arg)
+                    .parse()
+                    .unwrap_or_else(|_| fail("the number of retries must be an integer"))
+            }
+            "-r" | "--recursive" => options.recursive = true,
+            "--checksum" => options.sync.checksum = true,
+            "--delete" => options.sync.delete = true,
+            "--dry-run" => options.sync.dry_run = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                fail(&format!("unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ if options.command.is_empty() => options.command = arg,
+            _ => options.args.push(arg),
+        }
+    }
+    options
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| fail(&format!("\"{}\" has no file name", path)))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), name)
+    }
+}
+
+// Runs the tasks on `jobs` threads, printing their messages,
+// and returns how many of them failed.
+fn run_parallel<T: Sync>(
+    tasks: &[T],
+    jobs: usize,
+    progress: &Progress,
+    run: impl Fn(&T) -> Result<String, String> + Sync,
+) -> usize {
+    let next = AtomicUsize::new(0);
+    let failures = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(tasks.len()) {
+            scope.spawn(|| {
+                while let Some(task) = tasks.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    match run(task) {
+                        Ok(message) => progress.println(&message),
+                        Err(message) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                            progress.eprintln(&message);
+                        }
+                    }
+                    progress.file_done();
+                }
+            });
+        }
+    });
+    progress.finish();
+    failures.into_inner()
+}
+
+fn local_size(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", path.display(), e)))
+        .len()
+}
+
+fn put(client: &Client, options: &Options) -> usize {
+    let (locals, remote) = match options.args.as_slice() {
+        [] => fail("missing file to upload"),
+        [local] => (std::slice::from_ref(local), file_name(local)),
+        [locals @ .., remote] => (locals, remote.clone()),
+    };
+    let into_directory = locals.len() > 1 || remote.ends_with('/');
+    let transfers = locals
+        .iter()
+        .map(|local| {
+            let remote = if into_directory {
+                join(&remote, &file_name(local))
+            } else {
+                remote.clone()
+            };
+            (PathBuf::from(local), remote)
+        })
+        .collect::<Vec<_>>();
+    let progress = Progress::new(
+        transfers.len(),
+        transfers.iter().map(|(local, _)| local_size(local)).sum(),
+    );
+    run_parallel(&transfers, options.jobs, &progress, |(local, remote)| {
+        client
+            .put(local, remote, &progress)
+            .map(|_| format!("Uploaded \"{}\"", remote))
+            .map_err(|e| format!("Cannot upload \"{}\": {}", remote, e))
+    })
+}
+
+fn get(client: &Client, options: &Options) -> usize {
+    let (remotes, local) = match options.args.as_slice() {
+        [] => fail("missing file to download"),
+        [remote] => (
+            std::slice::from_ref(remote),
+            PathBuf::from(file_name(remote)),
+        ),
+        [remotes @ .., local] => (remotes, PathBuf::from(local)),
+    };
+    let into_directory = remotes.len() > 1 || local.is_dir();
+    if remotes.len() > 1 && !local.is_dir() {
+        fail(&format!("{} is not a directory", local.display()));
+    }
+    let transfers = remotes
+        .iter()
+        .map(|remote| {
+            let local = if into_directory {
+                local.join(file_name(remote))
+            } else {
+                local.clone()
+            };
+            (remote.clone(), local)
+        })
+        .collect::<Vec<_>>();
+    // The sizes of the files are not known before they are downloaded.
+    let progress = Progress::new(transfers.len(), 0);
+    run_parallel(&transfers, options.jobs, &progress, |(remote, local)| {
+        client
+            .get(remote, local, &progress)
+            .map(|_| format!("Downloaded \"{}\" to {}", remote, local.display()))
+            .map_err(|e| format!("Cannot download \"{}\": {}", remote, e))
+    })
+}
+
+fn post(client: &Client, options: &Options) -> usize {
+    let (local, template) = match options.args.as_slice() {
+        [local] => (Path::new(local), ""),
+        [local, template] => (Path::new(local), template.as_str()),
+        _ => fail("post takes a file to upload and an optional name"),
+    };
+    let progress = Progress::new(1, local_size(local));
+    run_parallel(&[()], 1, &progress, |_| {
+        client
+            .post(local, template, &progress)
+            .map(|name| format!("Uploaded \"{}\"", name))
+            .map_err(|e| format!("Cannot upload {}: {}", local.display(), e))
+    })
+}
+
+fn rm(client: &Client, options: &Options) -> usize {
+    if options.args.is_empty() {
+        fail("missing file to delete");
+    }
+    let progress = Progress::hidden();
+    run_parallel(&options.args, options.jobs, &progress, |remote| {
+        client
+            .delete(remote, options.recursive)
+            .map(|_| format!("Deleted \"{}\"", remote))
+            .map_err(|e| format!("Cannot delete \"{}\": {}", remote, e))
+    })
+}
+
+fn ls(client: &Client, options: &Options) -> usize {
+    let remote = match options.args.as_slice() {
+        [] => "",
+        [remote] => remote.as_str(),
+        _ => fail("ls takes a single directory"),
+    };
+    match client.list(remote) {
+        Ok(entries) => {
+            for entry in entries {
+                let modified = entry
+                    .modified
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                match entry.entry_type {
+                    EntryType::Directory => {
+                        println!("{:>12}  {:19}  {}/", "", modified, entry.name)
+                    }
+                    EntryType::File => {
+                        println!("{:>12}  {:19}  {}", entry.size, modified, entry.name)
+                    }
+                }
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Cannot list \"{}\": {}", remote, e);
+            1
+        }
+    }
+}
+
+fn sync(client: &Client, options: &Options) -> Result<usize, ClientError> {
+    let (local_dir, remote_dir) = match options.args.as_slice() {
+        [local_dir] => (Path::new(local_dir), ""),
+        [local_dir, remote_dir] => (Path::new(local_dir), remote_dir.trim_matches('/')),
+        _ => fail("sync takes a local directory and an optional remote directory"),
+    };
+    if !local_dir.is_dir() {
+        fail(&format!("{} is not a directory", local_dir.display()));
+    }
+    let plan = sync::plan(client, local_dir, remote_dir, options.sync)?;
+    let remote = |relative: &str| join(remote_dir, relative);
+    let extra = if options.sync.delete {
+        plan.extra.as_slice()
+    } else {
+        &[]
+    };
+    if plan.replaced.is_empty()
+        && plan.directories.is_empty()
+        && plan.uploads.is_empty()
+        && extra.is_empty()
+    {
+        println!("Everything is up to date");
+        return Ok(0);
+    }
+    if options.sync.dry_run {
+        for path in plan.replaced.iter().chain(extra) {
+            println!("Would delete \"{}\"", remote(path));
+        }
+        for dir in &plan.directories {
+            println!("Would create \"{}\"", remote(dir));
+        }
+        for (path, _) in &plan.uploads {
+            println!("Would upload \"{}\"", remote(path));
+        }
+        return Ok(0);
+    }
+
+    // Entries changing type cannot be kept, even without --delete.
+    for path in &plan.replaced {
+        client.delete(&remote(path), true)?;
+        println!("Deleted \"{}\"", remote(path));
+    }
+    if !remote_dir.is_empty() {
+        let mut ancestor = String::new();
+        for segment in remote_dir.split('/') {
+            ancestor = join(&ancestor, segment);
+            client.mkdir(&ancestor)?;
+        }
+    }
+    for dir in &plan.directories {
+        client.mkdir(&remote(dir))?;
+        println!("Created \"{}\"", remote(dir));
+    }
+    let progress = Progress::new(
+        plan.uploads.len(),
+        plan.uploads.iter().map(|(_, local)| local.size).sum(),
+    );
+    let mut failures = run_parallel(&plan.uploads, options.jobs, &progress, |(path, local)| {
+        client
+            .put(&local.path, &remote(path), &progress)
+            .map(|_| format!("Uploaded \"{}\"", remote(path)))
+            .map_err(|e| format!("Cannot upload \"{}\": {}", remote(path), e))
+    });
+    for path in extra {
+        match client.delete(&remote(path), true) {
+            Ok(()) => println!("Deleted \"{}\"", remote(path)),
+            Err(e) => {
+                eprintln!("Cannot delete \"{}\": {}", remote(path), e);
+                failures += 1;
+            }
+        }
+    }
+    if !options.sync.delete && !plan.extra.is_empty() {
+        println!(
+            "Kept {} remote entries missing locally, which --delete would remove",
+            plan.extra.len()
+        );
+    }
+    Ok(failures)
+}
+
+fn main() {
+    let options = parse_args();
+    let client = Client::new(
+        &options.server,
+        options.credentials.as_deref(),
+        options.retries,
+    )
+    .unwrap_or_else(|e| fail(&e.to_string()));
+    let failures = match options.command.as_str() {
+        "put" => put(&client, &options),
+        "get" => get(&client, &options),
+        "post" => post(&client, &options),
+        "rm" => rm(&client, &options),
+        "ls" => ls(&client, &options),
+        "sync" => sync(&client, &options).unwrap_or_else(|e| fail(&e.to_string())),
+        "" => fail(&format!("missing command\n\n{}", USAGE)),
+        command => fail(&format!("unknown command {}\n\n{}", command, USAGE)),
+    };
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
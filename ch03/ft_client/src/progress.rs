@@ -0,0 +1,145 @@
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 30;
+// Minimum time between two redraws of the bar.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+struct State {
+    total_files: usize,
+    done_files: usize,
+    total_bytes: u64,
+    done_bytes: u64,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+// Progress of a set of transfers, possibly running in parallel, drawn as a single bar.
+// The bar is drawn only when the standard error is a terminal,
+// and the messages are printed above it.
+// The clones share the same bar.
+#[derive(Clone)]
+pub struct Progress {
+    state: Arc<Mutex<State>>,
+    visible: bool,
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", value, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl Progress {
+    pub fn new(total_files: usize, total_bytes: u64) -> Progress {
+        Progress {
+            state: Arc::new(Mutex::new(State {
+                total_files,
+                done_files: 0,
+                total_bytes,
+                done_bytes: 0,
+                start: Instant::now(),
+                last_draw: None,
+            })),
+            visible: std::io::stderr().is_terminal(),
+        }
+    }
+
+    // A progress without bar, for the commands that do not transfer files.
+    pub fn hidden() -> Progress {
+        Progress {
+            visible: false,
+            ..Progress::new(0, 0)
+        }
+    }
+
+    fn draw(&self, state: &mut State, force: bool) {
+        if !self.visible {
+            return;
+        }
+        let now = Instant::now();
+        if !force
+            && state
+                .last_draw
+                .is_some_and(|last| now - last < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        state.last_draw = Some(now);
+        let ratio = if state.total_bytes == 0 {
+            1.0
+        } else {
+            (state.done_bytes as f64 / state.total_bytes as f64).min(1.0)
+        };
+        let filled = (ratio * BAR_WIDTH as f64) as usize;
+        let seconds = (now - state.start).as_secs_f64().max(0.001);
+        eprint!(
+            "\r[{}{}] {:3.0}% {}/{} {}/s {}/{} files\x1b[K",
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            ratio * 100.0,
+            format_bytes(state.done_bytes as f64),
+            format_bytes(state.total_bytes as f64),
+            format_bytes(state.done_bytes as f64 / seconds),
+            state.done_files,
+            state.total_files
+        );
+        let _ = std::io::stderr().flush();
+    }
+
+    pub fn add(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.done_bytes += bytes;
+        self.draw(&mut state, false);
+    }
+
+    // Takes back the bytes of a transfer which has to start again.
+    pub fn rewind(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.done_bytes = state.done_bytes.saturating_sub(bytes);
+        self.draw(&mut state, false);
+    }
+
+    pub fn file_done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.done_files += 1;
+        self.draw(&mut state, true);
+    }
+
+    pub fn println(&self, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        if self.visible {
+            eprint!("\r\x1b[K");
+        }
+        println!("{}", message);
+        self.draw(&mut state, true);
+    }
+
+    pub fn eprintln(&self, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        if self.visible {
+            eprint!("\r\x1b[K");
+        }
+        eprintln!("{}", message);
+        self.draw(&mut state, true);
+    }
+
+    // Draws the final state of the bar and leaves it on its own line.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if self.visible {
+            self.draw(&mut state, true);
+            eprintln!();
+        }
+    }
+}
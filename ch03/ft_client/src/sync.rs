@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::client::{self, Client, ClientError, EntryType};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    // Compares the files by hash rather than by size and modification time.
+    pub checksum: bool,
+    // Deletes the remote files which do not exist locally.
+    pub delete: bool,
+    // Only prints what would be done.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub size: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+struct RemoteFile {
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+// What is needed to make the remote directory a copy of the local one.
+// The paths are relative to the synchronized directories, with slashes as separators.
+#[derive(Debug, Default)]
+pub struct Plan {
+    // Remote entries whose type differs from the local one, deleted first.
+    pub replaced: Vec<String>,
+    pub directories: Vec<String>,
+    pub uploads: Vec<(String, LocalFile)>,
+    // Remote entries which do not exist locally.
+    pub extra: Vec<String>,
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// Lists the files and directories under `dir`, skipping the hidden ones,
+// which the server refuses to store.
+fn scan_local(
+    dir: &Path,
+    relative: &str,
+    files: &mut BTreeMap<String, LocalFile>,
+    directories: &mut Vec<String>,
+) -> Result<(), ClientError> {
+    let local_error = |e| ClientError::Local(dir.to_path_buf(), e);
+    for entry in std::fs::read_dir(dir).map_err(local_error)? {
+        let entry = entry.map_err(local_error)?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let metadata = std::fs::metadata(&path).map_err(|e| ClientError::Local(path.clone(), e))?;
+        let relative = join(relative, &name);
+        if metadata.is_dir() {
+            directories.push(relative.clone());
+            scan_local(&path, &relative, files, directories)?;
+        } else if metadata.is_file() {
+            files.insert(
+                relative,
+                LocalFile {
+                    path,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok().map(DateTime::from),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+// Lists the remote files and directories under `root`, which may not exist yet.
+fn scan_remote(
+    client: &Client,
+    root: &str,
+    relative: &str,
+    files: &mut BTreeMap<String, RemoteFile>,
+    directories: &mut Vec<String>,
+) -> Result<(), ClientError> {
+    let entries = match client.list(&join(root, relative)) {
+        Ok(entries) => entries,
+        Err(e) if e.is_not_found() && relative.is_empty() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let relative = join(relative, &entry.name);
+        match entry.entry_type {
+            EntryType::Directory => {
+                directories.push(relative.clone());
+                scan_remote(client, root, &relative, files, directories)?;
+            }
+            EntryType::File => {
+                files.insert(
+                    relative,
+                    RemoteFile {
+                        size: entry.size,
+                        modified: entry.modified,
+                    },
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Whether the remote copy of a local file is outdated.
+fn differs(
+    client: &Client,
+    remote: &str,
+    local: &LocalFile,
+    remote_file: &RemoteFile,
+    options: SyncOptions,
+) -> Result<bool, ClientError> {
+    if local.size != remote_file.size {
+        return Ok(true);
+    }
+    if options.checksum {
+        let local_hash = client::sha256_of_file(&local.path)
+            .map_err(|e| ClientError::Local(local.path.clone(), e))?;
+        return Ok(client.sha256(remote)?.as_ref() != Some(&local_hash));
+    }
+    // The remote modification time is when the file was stored.
+    Ok(match (local.modified, remote_file.modified) {
+        (Some(local_time), Some(remote_time)) => local_time > remote_time,
+        _ => true,
+    })
+}
+
+pub fn plan(
+    client: &Client,
+    local_dir: &Path,
+    remote_dir: &str,
+    options: SyncOptions,
+) -> Result<Plan, ClientError> {
+    let mut local_files = BTreeMap::new();
+    let mut local_directories = vec![];
+    scan_local(local_dir, "", &mut local_files, &mut local_directories)?;
+    let mut remote_files = BTreeMap::new();
+    let mut remote_directories = vec![];
+    scan_remote(
+        client,
+        remote_dir,
+        "",
+        &mut remote_files,
+        &mut remote_directories,
+    )?;
+
+    let mut plan = Plan::default();
+    // Directories come before their contents, as they are sorted by path.
+    local_directories.sort();
+    for dir in &local_directories {
+        if !remote_directories.contains(dir) {
+            if remote_files.remove(dir).is_some() {
+                plan.replaced.push(dir.clone());
+            }
+            plan.directories.push(dir.clone());
+        }
+    }
+    for (relative, local) in local_files {
+        let remote = join(remote_dir, &relative);
+        let upload = match remote_files.remove(&relative) {
+            Some(remote_file) => differs(client, &remote, &local, &remote_file, options)?,
+            None => {
+                if let Some(n) = remote_directories.iter().position(|d| *d == relative) {
+                    plan.replaced.push(remote_directories.remove(n));
+                }
+                true
+            }
+        };
+        if upload {
+            plan.uploads.push((relative, local));
+        }
+    }
+
+    // The remaining remote entries have no local counterpart,
+    // except those inside a deleted directory, which go with it.
+    let mut extra = remote_directories
+        .into_iter()
+        .filter(|d| !local_directories.contains(d))
+        .chain(remote_files.into_keys())
+        .collect::<Vec<_>>();
+    extra.sort();
+    for path in extra {
+        let inside = |dir: &String| path.starts_with(&format!("{}/", dir));
+        if !plan.replaced.iter().any(inside) && !plan.extra.iter().any(inside) {
+            plan.extra.push(path);
+        }
+    }
+    Ok(plan)
+}
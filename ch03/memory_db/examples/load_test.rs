@@ -0,0 +1,309 @@
+// Measures the throughput of a running memory_db or persons_db server under a mixed workload.
+//
+// Usage: cargo run --release --example load_test -- [server] [address] [clients] [seconds] [write_percent]
+//
+// `server` is "memory_db", the default, or "persons_db", whose address defaults to its port.
+// persons_db limits the rate of the requests of each user, so it has to be started
+// with a configuration raising the limits of its `[rate_limit]` section.
+//
+// Every client keeps a connection open and loops, either reading a person by id
+// or, with the given probability, updating one, and the totals are printed at the end.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Number of persons inserted before the measurement.
+const PERSON_COUNT: u32 = 1000;
+
+#[derive(Debug, Clone, Copy)]
+enum Server {
+    MemoryDb,
+    PersonsDb,
+}
+
+impl Server {
+    fn parse(name: &str) -> Option<Server> {
+        match name {
+            "memory_db" => Some(Server::MemoryDb),
+            "persons_db" => Some(Server::PersonsDb),
+            _ => None,
+        }
+    }
+
+    fn default_address(self) -> &'static str {
+        match self {
+            Server::MemoryDb => "127.0.0.1:8080",
+            Server::PersonsDb => "127.0.0.1:3000",
+        }
+    }
+
+    // Header lines sent with every request.
+    fn headers(self) -> &'static str {
+        match self {
+            Server::MemoryDb => "",
+            // The credentials of a user of persons_db who can write.
+            Server::PersonsDb => "Authorization: Basic c3VzYW46eHN1c2Fu\r\n",
+        }
+    }
+
+    // Returns the id of the new person.
+    fn insert(self, connection: &mut Connection, name: &str) -> io::Result<u32> {
+        let body = format!("{{\"name\":\"{}\"}}", name);
+        let (expected, (status, body)) = match self {
+            Server::MemoryDb => (201, connection.request("POST", "/persons", "", &body)?),
+            Server::PersonsDb => (
+                200,
+                connection.request("POST", "/one_person", self.headers(), &body)?,
+            ),
+        };
+        if status != expected {
+            return Err(io::Error::other(format!(
+                "insertion failed with status {}: {}",
+                status, body
+            )));
+        }
+        // memory_db returns the new person, and persons_db only its id.
+        let id = match self {
+            Server::MemoryDb => body.split("\"id\":").nth(1),
+            Server::PersonsDb => Some(body.as_str()),
+        };
+        id.and_then(|s| s.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, body.clone()))
+    }
+
+    // Returns the status of the response.
+    fn read(self, connection: &mut Connection, id: u32) -> io::Result<u16> {
+        let path = format!("/person/{}", id);
+        Ok(connection.request("GET", &path, self.headers(), "")?.0)
+    }
+
+    // Returns the status of the response.
+    // The updates of persons_db are not based on a version, as the clients overwrite each other.
+    fn update(self, connection: &mut Connection, id: u32, name: &str) -> io::Result<u16> {
+        let (status, _) = match self {
+            Server::MemoryDb => connection.request(
+                "PUT",
+                &format!("/person/{}", id),
+                "",
+                &format!("{{\"name\":\"{}\"}}", name),
+            )?,
+            Server::PersonsDb => connection.request(
+                "PUT",
+                "/one_person",
+                &format!("{}If-Match: *\r\n", self.headers()),
+                &format!("{{\"id\":{},\"name\":\"{}\"}}", id, name),
+            )?,
+        };
+        Ok(status)
+    }
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Connection> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    // Sends a request with the given header lines,
+    // and returns the status code and the body of the response.
+    fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &str,
+        body: &str,
+    ) -> io::Result<(u16, String)> {
+        write!(
+            self.writer,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        )?;
+
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let status = line
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, line.clone()))?;
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            self.reader.read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.reader.read_exact(&mut body)?;
+        Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    reads: u64,
+    writes: u64,
+    errors: u64,
+    latencies: Vec<Duration>,
+}
+
+// A small xorshift generator, good enough to pick ids and operations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn run_client(
+    server: Server,
+    address: &str,
+    seed: u64,
+    write_percent: u64,
+    ids: &[u32],
+    stop: &AtomicBool,
+) -> io::Result<Stats> {
+    let mut connection = Connection::open(address)?;
+    let mut rng = Rng(seed);
+    let mut stats = Stats::default();
+    while !stop.load(Ordering::Relaxed) {
+        let id = ids[(rng.next() % ids.len() as u64) as usize];
+        let is_write = rng.next() % 100 < write_percent;
+        let start = Instant::now();
+        let status = if is_write {
+            let name = format!("person {}", rng.next() % 100_000);
+            server.update(&mut connection, id, &name)?
+        } else {
+            server.read(&mut connection, id)?
+        };
+        stats.latencies.push(start.elapsed());
+        if status != 200 {
+            stats.errors += 1;
+        } else if is_write {
+            stats.writes += 1;
+        } else {
+            stats.reads += 1;
+        }
+    }
+    Ok(stats)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let server_name = args.get(1).map(String::as_str).unwrap_or("memory_db");
+    let server = Server::parse(server_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown server \"{}\", expected memory_db or persons_db",
+            server_name
+        );
+        std::process::exit(1);
+    });
+    let address = args
+        .get(2)
+        .cloned()
+        .unwrap_or(server.default_address().to_string());
+    let clients: u64 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(8);
+    let seconds: u64 = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(10);
+    let write_percent: u64 = args.get(5).and_then(|s| s.parse().ok()).unwrap_or(10);
+
+    println!(
+        "Inserting {} persons into {:?} at {} ...",
+        PERSON_COUNT, server, address
+    );
+    let mut connection = Connection::open(&address)?;
+    let ids = (0..PERSON_COUNT)
+        .map(|n| server.insert(&mut connection, &format!("person {}", n)))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot insert the persons: {}", e);
+            std::process::exit(1);
+        });
+    let ids = Arc::new(ids);
+
+    println!(
+        "Running {} clients for {} s with {}% writes ...",
+        clients, seconds, write_percent
+    );
+    let stop = Arc::new(AtomicBool::new(false));
+    let handles = (0..clients)
+        .map(|n| {
+            let address = address.clone();
+            let ids = ids.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                run_client(
+                    server,
+                    &address,
+                    0x9E37_79B9_7F4A_7C15 ^ (n + 1),
+                    write_percent,
+                    &ids,
+                    &stop,
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    thread::sleep(Duration::from_secs(seconds));
+    stop.store(true, Ordering::Relaxed);
+
+    let mut total = Stats::default();
+    for handle in handles {
+        let stats = handle.join().unwrap()?;
+        total.reads += stats.reads;
+        total.writes += stats.writes;
+        total.errors += stats.errors;
+        total.latencies.extend(stats.latencies);
+    }
+    total.latencies.sort();
+
+    let requests = total.reads + total.writes + total.errors;
+    println!("Requests:   {}", requests);
+    println!(
+        "Throughput: {:.0} requests/s ({:.0} reads/s, {:.0} writes/s)",
+        requests as f64 / seconds as f64,
+        total.reads as f64 / seconds as f64,
+        total.writes as f64 / seconds as f64
+    );
+    println!("Errors:     {}", total.errors);
+    println!(
+        "Latency:    p50 {:?}, p99 {:?}, max {:?}",
+        percentile(&total.latencies, 0.5),
+        percentile(&total.latencies, 0.99),
+        percentile(&total.latencies, 1.0)
+    );
+    Ok(())
+}
@@ -1,46 +1,227 @@
-#[derive(Debug)]
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::search::{MatchKind, NameIndex};
+use crate::wal::{Wal, WalCheck, WalRecord};
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Person {
     pub id: u32,
     pub name: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PersonMatch<'a> {
+    #[serde(flatten)]
+    pub person: &'a Person,
+    #[serde(rename = "match")]
+    pub kind: MatchKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Insert { name: String },
+    Update { id: u32, name: String },
+    Delete { id: u32 },
+}
+
+#[derive(Debug)]
+pub enum BatchError {
+    // The operation at `index` cannot be applied, so none is.
+    Rejected {
+        index: usize,
+        not_found: bool,
+        message: String,
+    },
+    Io(io::Error),
+}
+
+impl From<io::Error> for BatchError {
+    fn from(e: io::Error) -> BatchError {
+        BatchError::Io(e)
+    }
+}
+
 pub struct DbConnection {
-    persons: Vec<Person>,
+    // Indexed by id, and kept in id order for listing.
+    persons: BTreeMap<u32, Person>,
+    names: NameIndex,
+    // Ids are never reused, even those of deleted persons,
+    // as clients may still hold them.
+    next_id: u32,
+    wal: Option<Wal>,
 }
 
 impl DbConnection {
     pub fn new() -> DbConnection {
-        DbConnection { persons: vec![] }
+        DbConnection {
+            persons: BTreeMap::new(),
+            names: NameIndex::default(),
+            next_id: 1,
+            wal: None,
+        }
+    }
+
+    // Opens a database whose changes are persisted in the log at `wal_path`.
+    pub fn open(wal_path: &Path) -> io::Result<DbConnection> {
+        let (wal, records) = Wal::open(wal_path)?;
+        let mut db_conn = DbConnection::new();
+        for record in records {
+            db_conn.apply(record);
+        }
+        db_conn.wal = Some(wal);
+        Ok(db_conn)
+    }
+
+    // The check that the changes can still be persisted, if they are.
+    pub fn check_log(&self) -> Option<WalCheck> {
+        self.wal.as_ref().map(Wal::check)
     }
 
     pub fn get_all_persons_ids(&self) -> impl Iterator<Item = u32> + '_ {
-        self.persons.iter().map(|p| p.id)
+        self.persons.keys().copied()
+    }
+
+    pub fn get_person_by_id(&self, id: u32) -> Option<Person> {
+        self.persons.get(&id).cloned()
     }
 
-    pub fn get_person_name_by_id(&self, id: u32) -> Option<String> {
-        Some(self.persons.iter().find(|p| p.id == id)?.name.clone())
+    pub fn get_persons_by_partial_name(&self, subname: &str) -> Vec<&Person> {
+        if subname.is_empty() {
+            return self.persons.values().collect();
+        }
+        self.names
+            .substring_candidates(subname)
+            .into_iter()
+            .map(|id| &self.persons[&id])
+            .filter(|p| p.name.contains(subname))
+            .collect()
     }
 
-    pub fn get_persons_id_and_name_by_partial_name<'a>(
-        &'a self,
-        subname: &'a str,
-    ) -> impl Iterator<Item = (u32, String)> + 'a {
-        self.persons
-            .iter()
-            .filter(move |p| p.name.contains(subname))
-            .map(|p| (p.id, p.name.clone()))
+    // Returns at most `limit` persons whose name matches `query`, the best matches first.
+    pub fn search_persons(&self, query: &str, limit: usize) -> Vec<PersonMatch<'_>> {
+        self.names
+            .search(query)
+            .into_iter()
+            .take(limit)
+            .map(|hit| PersonMatch {
+                person: &self.persons[&hit.id],
+                kind: hit.kind,
+            })
+            .collect()
     }
 
-    pub fn insert_person(&mut self, name: &str) -> u32 {
-        let new_id = if self.persons.is_empty() {
-            1
-        } else {
-            self.persons[self.persons.len() - 1].id + 1
-        };
-        self.persons.push(Person {
+    pub fn insert_person(&mut self, name: &str) -> io::Result<u32> {
+        let new_id = self.next_id;
+        self.log_and_apply(WalRecord::Insert {
             id: new_id,
             name: name.to_string(),
-        });
-        new_id
+        })?;
+        Ok(new_id)
+    }
+
+    pub fn update_person(&mut self, id: u32, name: &str) -> io::Result<bool> {
+        if !self.persons.contains_key(&id) {
+            return Ok(false);
+        }
+        self.log_and_apply(WalRecord::Update {
+            id,
+            name: name.to_string(),
+        })?;
+        Ok(true)
+    }
+
+    pub fn delete_person(&mut self, id: u32) -> io::Result<bool> {
+        if !self.persons.contains_key(&id) {
+            return Ok(false);
+        }
+        self.log_and_apply(WalRecord::Delete { id })?;
+        Ok(true)
+    }
+
+    // Applies all the operations, or none of them if any cannot be applied.
+    // Returns the applied changes, including the ids of the inserted persons.
+    pub fn apply_batch(
+        &mut self,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<WalRecord>, BatchError> {
+        let mut ids = self.persons.keys().copied().collect::<BTreeSet<_>>();
+        let mut next_id = self.next_id;
+        let mut records = vec![];
+        for (index, operation) in operations.into_iter().enumerate() {
+            let rejected = |not_found: bool, message: String| BatchError::Rejected {
+                index,
+                not_found,
+                message,
+            };
+            let record = match operation {
+                BatchOperation::Insert { name } => {
+                    let id = next_id;
+                    next_id += 1;
+                    ids.insert(id);
+                    WalRecord::Insert { id, name }
+                }
+                BatchOperation::Update { id, name } => {
+                    if !ids.contains(&id) {
+                        return Err(rejected(true, format!("Person {} not found.", id)));
+                    }
+                    WalRecord::Update { id, name }
+                }
+                BatchOperation::Delete { id } => {
+                    if !ids.remove(&id) {
+                        return Err(rejected(true, format!("Person {} not found.", id)));
+                    }
+                    WalRecord::Delete { id }
+                }
+            };
+            if let WalRecord::Insert { name, .. } | WalRecord::Update { name, .. } = &record {
+                if name.trim().is_empty() {
+                    return Err(rejected(false, "The name must not be empty.".to_string()));
+                }
+            }
+            records.push(record);
+        }
+        // A single log record makes the batch atomic even if the server crashes.
+        self.log_and_apply(WalRecord::Batch {
+            records: records.clone(),
+        })?;
+        Ok(records)
+    }
+
+    fn log_and_apply(&mut self, record: WalRecord) -> io::Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.append(&record)?;
+        }
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: WalRecord) {
+        match record {
+            WalRecord::Insert { id, name } => {
+                self.next_id = self.next_id.max(id + 1);
+                self.names.insert(id, &name);
+                self.persons.insert(id, Person { id, name });
+            }
+            WalRecord::Update { id, name } => {
+                if let Some(person) = self.persons.get_mut(&id) {
+                    self.names.insert(id, &name);
+                    person.name = name;
+                }
+            }
+            WalRecord::Delete { id } => {
+                self.names.remove(id);
+                self.persons.remove(&id);
+            }
+            WalRecord::Batch { records } => {
+                for record in records {
+                    self.apply(record);
+                }
+            }
+        }
     }
 }
@@ -1,29 +1,35 @@
 mod db_access;
+mod search;
+mod wal;
 
+use actix_cors::Cors;
 use actix_web::{web, web::Path, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use db_access::{BatchError, BatchOperation};
 use serde_derive::Deserialize;
-use std::sync::Mutex;
+use serde_json::json;
+use server_config::Config;
+use server_telemetry::Telemetry;
+use std::sync::RwLock;
+use wal::WalCheck;
+
+// Name of the write-ahead log file in the storage directory.
+const WAL_FILE: &str = "persons.wal";
+// Maximum number of search results returned when no limit is specified.
+const DEFAULT_SEARCH_LIMIT: usize = 20;
 
 struct AppState {
-    db: db_access::DbConnection,
+    db: RwLock<db_access::DbConnection>,
 }
 
-async fn get_all_persons_ids(state: web::Data<Mutex<AppState>>) -> impl Responder {
-    println!("In get_all_persons_ids()");
+async fn get_all_persons_ids(state: web::Data<AppState>) -> impl Responder {
+    log::debug!("get_all_persons_ids()");
 
-    let db_conn = &state.lock().unwrap().db;
-    db_conn
-        .get_all_persons_ids()
-        .map(|id| id.to_string())
-        .collect::<Vec<_>>()
-        .join(", ")
+    let db_conn = state.db.read().unwrap();
+    HttpResponse::Ok().json(db_conn.get_all_persons_ids().collect::<Vec<_>>())
 }
 
-async fn get_person_name_by_id(
-    state: web::Data<Mutex<AppState>>,
-    info: Path<(String,)>,
-) -> impl Responder {
-    println!("In get_person_name_by_id()");
+async fn get_person_by_id(state: web::Data<AppState>, info: Path<(String,)>) -> impl Responder {
+    log::debug!("get_person_by_id()");
 
     let id = &info.0;
     let id = id.parse::<u32>();
@@ -31,9 +37,9 @@ async fn get_person_name_by_id(
         return HttpResponse::NotFound().finish();
     }
     let id = id.unwrap();
-    let db_conn = &state.lock().unwrap().db;
-    if let Some(name) = db_conn.get_person_name_by_id(id) {
-        HttpResponse::Ok().content_type("text/plain").body(name)
+    let db_conn = state.db.read().unwrap();
+    if let Some(person) = db_conn.get_person_by_id(id) {
+        HttpResponse::Ok().json(person)
     } else {
         HttpResponse::NotFound().finish()
     }
@@ -44,59 +50,190 @@ pub struct Filter {
     partial_name: Option<String>,
 }
 
-async fn get_persons(
-    state: web::Data<Mutex<AppState>>,
-    query: web::Query<Filter>,
-) -> impl Responder {
-    println!("In get_persons");
-    println!(
-        "{}",
+async fn get_persons(state: web::Data<AppState>, query: web::Query<Filter>) -> impl Responder {
+    log::debug!(
+        "get_persons({})",
         &query.partial_name.clone().unwrap_or("default".to_string())
     );
 
-    let db_conn = &state.lock().unwrap().db;
-    db_conn
-        .get_persons_id_and_name_by_partial_name(
-            &query.partial_name.clone().unwrap_or("".to_string()),
-        )
-        .map(|p| p.0.to_string() + ": " + &p.1)
-        .collect::<Vec<_>>()
-        .join("; ")
+    let db_conn = state.db.read().unwrap();
+    let partial_name = &query.partial_name.clone().unwrap_or("".to_string());
+    HttpResponse::Ok().json(db_conn.get_persons_by_partial_name(partial_name))
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn search_persons(
+    state: web::Data<AppState>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    log::debug!("search_persons({})", &query.q);
+
+    if query.q.trim().is_empty() {
+        return HttpResponse::BadRequest().json("The query must not be empty.");
+    }
+    let db_conn = state.db.read().unwrap();
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    HttpResponse::Ok().json(db_conn.search_persons(&query.q, limit))
+}
+
+#[derive(Deserialize)]
+pub struct InsertingPerson {
+    name: String,
+}
+
+fn empty_name() -> HttpResponse {
+    HttpResponse::BadRequest().json("The name must not be empty.")
+}
+
+fn storage_error(error: std::io::Error) -> HttpResponse {
+    log::error!("Failed to write to the log: {}", error);
+    HttpResponse::InternalServerError().json("Failed to persist the change.")
+}
+
+async fn insert_person(
+    state: web::Data<AppState>,
+    person: web::Json<InsertingPerson>,
+) -> impl Responder {
+    log::debug!("insert_person()");
+
+    if person.name.trim().is_empty() {
+        return empty_name();
+    }
+    let mut db_conn = state.db.write().unwrap();
+    match db_conn.insert_person(&person.name) {
+        Ok(id) => HttpResponse::Created().json(db_conn.get_person_by_id(id)),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn update_person(
+    state: web::Data<AppState>,
+    info: Path<(u32,)>,
+    person: web::Json<InsertingPerson>,
+) -> impl Responder {
+    log::debug!("update_person()");
+
+    if person.name.trim().is_empty() {
+        return empty_name();
+    }
+    let id = info.0;
+    let mut db_conn = state.db.write().unwrap();
+    match db_conn.update_person(id, &person.name) {
+        Ok(true) => HttpResponse::Ok().json(db_conn.get_person_by_id(id)),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => storage_error(e),
+    }
 }
 
-async fn insert_person(state: web::Data<Mutex<AppState>>, info: Path<(String,)>) -> impl Responder {
-    println!("In insert_person()");
+async fn delete_person(state: web::Data<AppState>, info: Path<(u32,)>) -> impl Responder {
+    log::debug!("delete_person()");
 
-    let name = &info.0;
-    let db_conn = &mut state.lock().unwrap().db;
-    format!("{}", db_conn.insert_person(name))
+    let mut db_conn = state.db.write().unwrap();
+    match db_conn.delete_person(info.0) {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => storage_error(e),
+    }
+}
+
+async fn apply_batch(
+    state: web::Data<AppState>,
+    operations: web::Json<Vec<BatchOperation>>,
+) -> impl Responder {
+    log::debug!("apply_batch()");
+
+    let mut db_conn = state.db.write().unwrap();
+    match db_conn.apply_batch(operations.into_inner()) {
+        Ok(records) => HttpResponse::Ok().json(records),
+        Err(BatchError::Rejected {
+            index,
+            not_found,
+            message,
+        }) => {
+            let body = json!({ "index": index, "error": message });
+            if not_found {
+                HttpResponse::NotFound().json(body)
+            } else {
+                HttpResponse::BadRequest().json(body)
+            }
+        }
+        Err(BatchError::Io(e)) => storage_error(e),
+    }
 }
 
 async fn invalid_resource(req: HttpRequest) -> impl Responder {
-    println!("Invalid URI: \"{}\"", req.uri());
+    log::warn!("Invalid URI: \"{}\"", req.uri());
     HttpResponse::NotFound()
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let addr = "127.0.0.1:8080";
-    println!("Listening at address {}", addr);
-    let db_conn = web::Data::new(Mutex::new(AppState {
-        db: db_access::DbConnection::new(),
-    }));
+    let config = Config::load("MEMORY_DB", 8080).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    server_telemetry::init_logging(&config.log.level);
+
+    log::info!(
+        "Listening at address {}:{}",
+        config.server.bind_address,
+        config.server.port
+    );
+    let allowed_origins = config.server.allowed_origins.clone();
+    let db = match &config.storage.path {
+        Some(path) => {
+            let wal_path = path.join(WAL_FILE);
+            log::info!("Replaying the log \"{}\"", wal_path.display());
+            db_access::DbConnection::open(&wal_path)?
+        }
+        None => db_access::DbConnection::new(),
+    };
+    let db_conn = web::Data::new(AppState {
+        db: RwLock::new(db),
+    });
+    let telemetry = Telemetry::new({
+        let db_conn = db_conn.clone();
+        move || {
+            // The database is released before the check writes to the disk.
+            let check = db_conn
+                .db
+                .read()
+                .map_err(|_| "the database is poisoned by a panic".to_string())?
+                .check_log();
+            check.map_or(Ok(()), WalCheck::run)
+        }
+    });
     HttpServer::new(move || {
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin));
         App::new()
+            .wrap(cors)
+            .wrap(telemetry.middleware())
+            .configure(|cfg| telemetry.configure(cfg))
             .app_data(db_conn.clone())
+            .service(web::resource("/persons/search").route(web::get().to(search_persons)))
+            .service(web::resource("/persons/batch").route(web::post().to(apply_batch)))
             .service(web::resource("/persons/ids").route(web::get().to(get_all_persons_ids)))
             .service(
-                web::resource("/person/name_by_id/{id}")
-                    .route(web::get().to(get_person_name_by_id)),
+                web::resource("/persons")
+                    .route(web::get().to(get_persons))
+                    .route(web::post().to(insert_person)),
+            )
+            .service(
+                web::resource("/person/{id}")
+                    .route(web::get().to(get_person_by_id))
+                    .route(web::put().to(update_person))
+                    .route(web::delete().to(delete_person)),
             )
-            .service(web::resource("/persons").route(web::get().to(get_persons)))
-            .service(web::resource("/person/{name}").route(web::post().to(insert_person)))
             .default_service(web::route().to(invalid_resource))
     })
-    .bind(addr)?
+    .bind((config.server.bind_address, config.server.port))?
     .run()
     .await
 }
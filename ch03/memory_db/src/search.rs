@@ -0,0 +1,265 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde_derive::Serialize;
+
+// Names are indexed by all their n-grams up to this length.
+const MAX_GRAM_LENGTH: usize = 3;
+// Shorter queries are not searched approximately, as they would match almost anything.
+const MIN_FUZZY_QUERY_LENGTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Substring,
+    Fuzzy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchHit {
+    pub kind: MatchKind,
+    // Edit distance for fuzzy matches, position of the match otherwise.
+    pub rank: usize,
+    pub id: u32,
+}
+
+// Inverted index from the n-grams of the lowercased names to the ids of the persons.
+#[derive(Default)]
+pub struct NameIndex {
+    names: HashMap<u32, Vec<char>>,
+    postings: HashMap<String, BTreeSet<u32>>,
+}
+
+fn normalize(name: &str) -> Vec<char> {
+    name.to_lowercase().chars().collect()
+}
+
+// The distinct n-grams of `chars` of length `n`.
+fn grams(chars: &[char], n: usize) -> BTreeSet<String> {
+    chars.windows(n).map(|w| w.iter().collect()).collect()
+}
+
+fn all_grams(chars: &[char]) -> BTreeSet<String> {
+    (1..=MAX_GRAM_LENGTH)
+        .flat_map(|n| grams(chars, n))
+        .collect()
+}
+
+fn find(haystack: &[char], needle: &[char]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn max_edit_distance(query_length: usize) -> usize {
+    if query_length <= 4 {
+        1
+    } else {
+        2
+    }
+}
+
+impl NameIndex {
+    pub fn insert(&mut self, id: u32, name: &str) {
+        self.remove(id);
+        let chars = normalize(name);
+        for gram in all_grams(&chars) {
+            self.postings.entry(gram).or_default().insert(id);
+        }
+        self.names.insert(id, chars);
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(chars) = self.names.remove(&id) {
+            for gram in all_grams(&chars) {
+                if let Some(ids) = self.postings.get_mut(&gram) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        self.postings.remove(&gram);
+                    }
+                }
+            }
+        }
+    }
+
+    // Ids of the persons whose name may contain `query`, ignoring case.
+    // The query must not be empty.
+    pub fn substring_candidates(&self, query: &str) -> BTreeSet<u32> {
+        let query = normalize(query);
+        let mut lists = grams(&query, query.len().min(MAX_GRAM_LENGTH))
+            .into_iter()
+            .map(|gram| self.postings.get(&gram))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+        lists.sort_by_key(|ids| ids.len());
+        let Some((shortest, others)) = lists.split_first() else {
+            return BTreeSet::new();
+        };
+        shortest
+            .iter()
+            .filter(|id| others.iter().all(|ids| ids.contains(id)))
+            .copied()
+            .collect()
+    }
+
+    // Ranks exact matches first, then prefixes of the name or of one of its words,
+    // then other substrings, then names having a word within a small edit distance.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let query_chars = normalize(query.trim());
+        if query_chars.is_empty() {
+            return vec![];
+        }
+        let mut hits = vec![];
+        let candidates = self.substring_candidates(query.trim());
+        for &id in &candidates {
+            let name = &self.names[&id];
+            if let Some(position) = find(name, &query_chars) {
+                let kind = if *name == query_chars {
+                    MatchKind::Exact
+                } else if position == 0 || name[position - 1] == ' ' {
+                    MatchKind::Prefix
+                } else {
+                    MatchKind::Substring
+                };
+                hits.push(SearchHit {
+                    kind,
+                    rank: position,
+                    id,
+                });
+            }
+        }
+
+        if query_chars.len() >= MIN_FUZZY_QUERY_LENGTH {
+            let max_distance = max_edit_distance(query_chars.len());
+            // Every edit destroys at most two bigrams of the query,
+            // so a close enough name has to share the others with it.
+            let bigrams = grams(&query_chars, 2);
+            let min_shared = bigrams.len().saturating_sub(2 * max_distance).max(1);
+            let mut shared = HashMap::<u32, usize>::new();
+            for gram in &bigrams {
+                for &id in self.postings.get(gram).into_iter().flatten() {
+                    *shared.entry(id).or_default() += 1;
+                }
+            }
+            for (id, count) in shared {
+                if count < min_shared || candidates.contains(&id) {
+                    continue;
+                }
+                let name = &self.names[&id];
+                let distance = name
+                    .split(|c| *c == ' ')
+                    .chain(std::iter::once(name.as_slice()))
+                    .map(|word| edit_distance(&query_chars, word))
+                    .min()
+                    .unwrap_or(usize::MAX);
+                if distance <= max_distance {
+                    hits.push(SearchHit {
+                        kind: MatchKind::Fuzzy,
+                        rank: distance,
+                        id,
+                    });
+                }
+            }
+        }
+        hits.sort();
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> NameIndex {
+        let mut index = NameIndex::default();
+        for (id, name) in [
+            (1, "Ann Smith"),
+            (2, "Joanna"),
+            (3, "Anna"),
+            (4, "Bob"),
+            (5, "Hannah"),
+        ] {
+            index.insert(id, name);
+        }
+        index
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<u32> {
+        hits.iter().map(|hit| hit.id).collect()
+    }
+
+    #[test]
+    fn prefixes_rank_before_substrings() {
+        let hits = index().search("ANN");
+        assert_eq!(ids(&hits), [1, 3, 5, 2]);
+        assert_eq!(hits[0].kind, MatchKind::Prefix);
+        assert_eq!(hits[2].kind, MatchKind::Substring);
+        assert_eq!(hits[2].rank, 1);
+    }
+
+    #[test]
+    fn exact_match_ranks_first_and_fuzzy_last() {
+        let hits = index().search(" anna ");
+        assert_eq!(ids(&hits), [3, 5, 2, 1]);
+        let kinds = hits.iter().map(|hit| hit.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                MatchKind::Exact,
+                MatchKind::Substring,
+                MatchKind::Substring,
+                MatchKind::Fuzzy
+            ]
+        );
+    }
+
+    #[test]
+    fn words_are_matched_by_prefix_and_approximately() {
+        let index = index();
+        let hits = index.search("smi");
+        assert_eq!(ids(&hits), [1]);
+        assert_eq!((hits[0].kind, hits[0].rank), (MatchKind::Prefix, 4));
+
+        let hits = index.search("Smtih");
+        assert_eq!(ids(&hits), [1]);
+        assert_eq!((hits[0].kind, hits[0].rank), (MatchKind::Fuzzy, 2));
+    }
+
+    #[test]
+    fn short_and_empty_queries_are_not_searched_approximately() {
+        let index = index();
+        assert!(index.search("bx").is_empty());
+        assert_eq!(ids(&index.search("bo")), [4]);
+        assert!(index.search("  ").is_empty());
+    }
+
+    #[test]
+    fn index_follows_updates_and_deletions() {
+        let mut index = index();
+        index.insert(2, "Zoe");
+        assert!(!ids(&index.search("joanna")).contains(&2));
+        assert_eq!(ids(&index.search("zoe")), [2]);
+        assert!(!index.postings.contains_key("jo"));
+
+        index.remove(3);
+        assert!(!index.substring_candidates("anna").contains(&3));
+        assert!(index.substring_candidates("anna").contains(&5));
+
+        for id in [1, 2, 4, 5] {
+            index.remove(id);
+        }
+        assert!(index.names.is_empty() && index.postings.is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum WalRecord {
+    Insert { id: u32, name: String },
+    Update { id: u32, name: String },
+    Delete { id: u32 },
+    Batch { records: Vec<WalRecord> },
+}
+
+// Write-ahead log of the changes to the database, one JSON record per line.
+// Every change is durably appended before being applied in memory,
+// so that the state can be rebuilt by replaying the log.
+pub struct Wal {
+    file: File,
+    path: PathBuf,
+    // Length of the log up to its last complete record.
+    len: u64,
+    // Set when a failed append could not be undone, leaving garbage at the end of the log.
+    broken: bool,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Wal {
+    // Opens or creates the log, returning the records it contains.
+    // Only the last line can be left incomplete by a crash, and then it is discarded;
+    // any other invalid line is an error, as the records after it cannot be trusted.
+    pub fn open(path: &Path) -> io::Result<(Wal, Vec<WalRecord>)> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut records = vec![];
+        let mut valid_len = 0;
+        let mut reader = BufReader::new(&file);
+        let mut line = vec![];
+        let mut line_number = 0;
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            line_number += 1;
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            let record = serde_json::from_slice::<WalRecord>(&line).map_err(|e| {
+                invalid_data(format!(
+                    "invalid record at line {} of the log \"{}\": {}",
+                    line_number,
+                    path.display(),
+                    e
+                ))
+            })?;
+            records.push(record);
+            valid_len += line.len() as u64;
+            line.clear();
+        }
+        if valid_len < file.metadata()?.len() {
+            log::warn!(
+                "Discarding the incomplete last line of the log \"{}\"",
+                path.display()
+            );
+            file.set_len(valid_len)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok((
+            Wal {
+                file,
+                path: path.to_path_buf(),
+                len: valid_len,
+                broken: false,
+            },
+            records,
+        ))
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        if self.broken {
+            return Err(invalid_data(format!(
+                "the log \"{}\" has an incomplete record at its end",
+                self.path.display()
+            )));
+        }
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let result = self
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data());
+        match result {
+            Ok(()) => {
+                self.len += line.len() as u64;
+                Ok(())
+            }
+            // Removes what may have been written, so that the next record starts on its own line.
+            Err(e) => {
+                if let Err(truncate_error) = self.file.set_len(self.len) {
+                    log::error!(
+                        "Failed to truncate the log \"{}\": {}",
+                        self.path.display(),
+                        truncate_error
+                    );
+                    self.broken = true;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Returns the check that records can still be appended to the log,
+    // which does not hold the log while writing to the disk.
+    pub fn check(&self) -> WalCheck {
+        WalCheck {
+            path: self.path.clone(),
+            broken: self.broken,
+        }
+    }
+}
+
+pub struct WalCheck {
+    path: PathBuf,
+    broken: bool,
+}
+
+impl WalCheck {
+    pub fn run(self) -> Result<(), String> {
+        if self.broken {
+            return Err("the log has an incomplete record at its end".to_string());
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("cannot write to the log: {}", e))?;
+        let probe = self.path.with_extension("readyz");
+        std::fs::write(&probe, b"")
+            .map_err(|e| format!("cannot write to the log directory: {}", e))?;
+        std::fs::remove_file(&probe)
+            .map_err(|e| format!("cannot delete from the log directory: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A scratch directory holding the log of a test.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir =
+                std::env::temp_dir().join(format!("memory_db-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            Scratch(dir)
+        }
+
+        fn log(&self) -> PathBuf {
+            self.0.join("wal.jsonl")
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn to_json(records: &[WalRecord]) -> String {
+        serde_json::to_string(records).unwrap()
+    }
+
+    fn sample() -> Vec<WalRecord> {
+        vec![
+            WalRecord::Insert {
+                id: 1,
+                name: "Ann".to_string(),
+            },
+            WalRecord::Batch {
+                records: vec![
+                    WalRecord::Update {
+                        id: 1,
+                        name: "Ann Smith".to_string(),
+                    },
+                    WalRecord::Insert {
+                        id: 2,
+                        name: "Bob".to_string(),
+                    },
+                ],
+            },
+            WalRecord::Delete { id: 2 },
+        ]
+    }
+
+    #[test]
+    fn appended_records_are_replayed() {
+        let scratch = Scratch::new("replay");
+        let (mut wal, records) = Wal::open(&scratch.log()).unwrap();
+        assert!(records.is_empty());
+        for record in &sample() {
+            wal.append(record).unwrap();
+        }
+        drop(wal);
+
+        let (_, records) = Wal::open(&scratch.log()).unwrap();
+        assert_eq!(to_json(&records), to_json(&sample()));
+    }
+
+    #[test]
+    fn corrupt_line_is_rejected() {
+        let scratch = Scratch::new("corrupt");
+        let contents = "{\"op\":\"insert\",\"id\":1,\"name\":\"Ann\"}\n\
+                        {\"op\":\"explode\"}\n\
+                        {\"op\":\"delete\",\"id\":1}\n";
+        std::fs::create_dir_all(&scratch.0).unwrap();
+        std::fs::write(scratch.log(), contents).unwrap();
+
+        let error = Wal::open(&scratch.log()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 2"));
+        // Nothing is discarded, so that the log can be repaired.
+        assert_eq!(std::fs::read_to_string(scratch.log()).unwrap(), contents);
+    }
+
+    #[test]
+    fn corrupt_complete_last_line_is_rejected() {
+        let scratch = Scratch::new("corrupt-last");
+        std::fs::create_dir_all(&scratch.0).unwrap();
+        std::fs::write(
+            scratch.log(),
+            "{\"op\":\"insert\",\"id\":1,\"name\":\"Ann\"}\n{\"op\":\"ins\n",
+        )
+        .unwrap();
+
+        let error = Wal::open(&scratch.log()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn incomplete_last_line_is_truncated() {
+        let scratch = Scratch::new("truncated");
+        let complete = "{\"op\":\"insert\",\"id\":1,\"name\":\"Ann\"}\n";
+        std::fs::create_dir_all(&scratch.0).unwrap();
+        std::fs::write(
+            scratch.log(),
+            format!("{}{{\"op\":\"delete\",\"i", complete),
+        )
+        .unwrap();
+
+        let (mut wal, records) = Wal::open(&scratch.log()).unwrap();
+        assert_eq!(to_json(&records), to_json(&sample()[..1]));
+        assert_eq!(std::fs::read_to_string(scratch.log()).unwrap(), complete);
+
+        // The next record starts on its own line.
+        wal.append(&WalRecord::Delete { id: 1 }).unwrap();
+        drop(wal);
+        let (_, records) = Wal::open(&scratch.log()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[1], WalRecord::Delete { id: 1 }));
+    }
+
+    #[test]
+    fn check_passes_on_a_writable_log() {
+        let scratch = Scratch::new("check");
+        let (wal, _) = Wal::open(&scratch.log()).unwrap();
+        assert_eq!(wal.check().run(), Ok(()));
+        assert!(!scratch.log().with_extension("readyz").exists());
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use toml::{Table, Value};
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Server {
+    pub bind_address: String,
+    pub port: u16,
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server {
+            bind_address: "127.0.0.1".to_string(),
+            port: 8080,
+            allowed_origins: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Storage {
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Log {
+    pub level: String,
+}
+
+impl Default for Log {
+    fn default() -> Log {
+        Log {
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub server: Server,
+    #[serde(default)]
+    pub storage: Storage,
+    #[serde(default)]
+    pub log: Log,
+    // The remaining sections, whose contents are specific to each server.
+    #[serde(flatten)]
+    sections: Table,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(String),
+    Env(String, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "cannot read config file \"{}\": {}", path.display(), e)
+            }
+            ConfigError::Parse(msg) => write!(f, "malformed config: {}", msg),
+            ConfigError::Env(var, msg) => {
+                write!(f, "invalid environment variable {}: {}", var, msg)
+            }
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // Reads the config file given as first command-line argument,
+    // or in the `<env_prefix>_CONFIG` variable, or "config.toml" if it exists.
+    // Then the variables `<env_prefix>_BIND_ADDRESS`, `<env_prefix>_PORT`,
+    // `<env_prefix>_ALLOWED_ORIGINS` (comma-separated), `<env_prefix>_STORAGE_PATH`
+    // and `<env_prefix>_LOG_LEVEL` override the values of the file.
+    pub fn load(env_prefix: &str, default_port: u16) -> Result<Config, ConfigError> {
+        let path = std::env::args()
+            .nth(1)
+            .or_else(|| std::env::var(format!("{}_CONFIG", env_prefix)).ok())
+            .map(PathBuf::from);
+        Config::read(path, env_prefix, default_port)
+    }
+
+    fn read(
+        path: Option<PathBuf>,
+        env_prefix: &str,
+        default_port: u16,
+    ) -> Result<Config, ConfigError> {
+        let text = match path {
+            Some(path) => std::fs::read_to_string(&path).map_err(|e| ConfigError::Read(path, e))?,
+            None => std::fs::read_to_string(DEFAULT_CONFIG_FILE).unwrap_or_default(),
+        };
+        Config::from_toml(&text, env_prefix, default_port)
+    }
+
+    fn from_toml(text: &str, env_prefix: &str, default_port: u16) -> Result<Config, ConfigError> {
+        let mut table = text
+            .parse::<Table>()
+            .map_err(|e| ConfigError::Parse(e.to_string()))?;
+        set_default(
+            &mut table,
+            "server",
+            "port",
+            Value::Integer(default_port.into()),
+        );
+
+        let overrides: [(&str, &str, &str); 5] = [
+            ("BIND_ADDRESS", "server", "bind_address"),
+            ("PORT", "server", "port"),
+            ("ALLOWED_ORIGINS", "server", "allowed_origins"),
+            ("STORAGE_PATH", "storage", "path"),
+            ("LOG_LEVEL", "log", "level"),
+        ];
+        for (suffix, section, key) in overrides {
+            let var = format!("{}_{}", env_prefix, suffix);
+            if let Ok(text) = std::env::var(&var) {
+                let value = match key {
+                    "port" => Value::Integer(
+                        text.parse::<u16>()
+                            .map_err(|e| ConfigError::Env(var.clone(), e.to_string()))?
+                            .into(),
+                    ),
+                    "allowed_origins" => Value::Array(
+                        text.split(',')
+                            .map(str::trim)
+                            .filter(|o| !o.is_empty())
+                            .map(|o| Value::String(o.to_string()))
+                            .collect(),
+                    ),
+                    _ => Value::String(text),
+                };
+                set(&mut table, section, key, value);
+            }
+        }
+
+        let config: Config = table
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let server = &self.server;
+        if server.port == 0 {
+            return Err(ConfigError::Invalid(
+                "server.port must not be 0".to_string(),
+            ));
+        }
+        if (server.bind_address.as_str(), server.port)
+            .to_socket_addrs()
+            .is_err()
+        {
+            return Err(ConfigError::Invalid(format!(
+                "server.bind_address \"{}\" is not a valid address",
+                server.bind_address
+            )));
+        }
+        if let Some(origin) = server
+            .allowed_origins
+            .iter()
+            .find(|o| !(o.starts_with("http://") || o.starts_with("https://")) || o.ends_with('/'))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "server.allowed_origins contains \"{}\", which is not of the form \"http[s]://host[:port]\"",
+                origin
+            )));
+        }
+        if let Some(path) = &self.storage.path {
+            if path.exists() && !path.is_dir() {
+                return Err(ConfigError::Invalid(format!(
+                    "storage.path \"{}\" is not a directory",
+                    path.display()
+                )));
+            }
+        }
+        if !LOG_LEVELS.contains(&self.log.level.to_lowercase().as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "log.level \"{}\" is not one of {}",
+                self.log.level,
+                LOG_LEVELS.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    // Deserializes a section specific to a server, which may be missing.
+    pub fn section<T: DeserializeOwned>(&self, name: &str) -> Result<T, ConfigError> {
+        self.sections
+            .get(name)
+            .cloned()
+            .unwrap_or(Value::Table(Table::new()))
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Parse(format!("[{}]: {}", name, e)))
+    }
+}
+
+fn set_default(table: &mut Table, section: &str, key: &str, value: Value) {
+    if let Value::Table(section) = table
+        .entry(section)
+        .or_insert_with(|| Value::Table(Table::new()))
+    {
+        section.entry(key).or_insert(value);
+    }
+}
+
+fn set(table: &mut Table, section: &str, key: &str, value: Value) {
+    if let Value::Table(section) = table
+        .entry(section)
+        .or_insert_with(|| Value::Table(Table::new()))
+    {
+        section.insert(key.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test uses its own prefix, as the environment is shared by the tests.
+    fn from_toml(text: &str, env_prefix: &str) -> Result<Config, ConfigError> {
+        Config::from_toml(text, env_prefix, 3000)
+    }
+
+    fn invalid(text: &str) -> String {
+        match from_toml(text, "SERVER_CONFIG_TEST_INVALID") {
+            Err(ConfigError::Invalid(msg)) => msg,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Limits {
+        max_size: u64,
+    }
+
+    #[test]
+    fn missing_values_have_defaults() {
+        let config = from_toml("", "SERVER_CONFIG_TEST_DEFAULTS").unwrap();
+        assert_eq!(config.server.bind_address, "127.0.0.1");
+        assert_eq!(config.server.port, 3000);
+        assert!(config.server.allowed_origins.is_empty());
+        assert_eq!(config.storage.path, None);
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.section::<Limits>("limits").unwrap().max_size, 0);
+    }
+
+    #[test]
+    fn file_values_and_sections_are_read() {
+        let config = from_toml(
+            "[server]\nport = 8081\nallowed_origins = [\"http://localhost:8080\"]\n\
+             [limits]\nmax_size = 10\n",
+            "SERVER_CONFIG_TEST_FILE",
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 8081);
+        assert_eq!(config.server.allowed_origins, ["http://localhost:8080"]);
+        assert_eq!(config.section::<Limits>("limits").unwrap().max_size, 10);
+
+        let config = from_toml("[limits]\nmax_size = \"big\"\n", "SERVER_CONFIG_TEST_FILE");
+        assert!(matches!(
+            config.unwrap().section::<Limits>("limits"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        std::env::set_var("SERVER_CONFIG_TEST_ENV_PORT", "9000");
+        std::env::set_var(
+            "SERVER_CONFIG_TEST_ENV_ALLOWED_ORIGINS",
+            "http://a.example, https://b.example:8443,",
+        );
+        std::env::set_var("SERVER_CONFIG_TEST_ENV_LOG_LEVEL", "debug");
+        std::env::set_var("SERVER_CONFIG_TEST_ENV_STORAGE_PATH", "data");
+        let config = from_toml(
+            "[server]\nport = 8081\n[log]\nlevel = \"warn\"\n",
+            "SERVER_CONFIG_TEST_ENV",
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(
+            config.server.allowed_origins,
+            ["http://a.example", "https://b.example:8443"]
+        );
+        assert_eq!(config.log.level, "debug");
+        assert_eq!(config.storage.path, Some(PathBuf::from("data")));
+    }
+
+    #[test]
+    fn invalid_environment_variable_is_named() {
+        std::env::set_var("SERVER_CONFIG_TEST_BAD_ENV_PORT", "eighty");
+        match from_toml("", "SERVER_CONFIG_TEST_BAD_ENV") {
+            Err(ConfigError::Env(var, _)) => assert_eq!(var, "SERVER_CONFIG_TEST_BAD_ENV_PORT"),
+            other => panic!("expected an invalid variable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(invalid("[server]\nport = 0\n").contains("server.port"));
+        assert!(invalid("[server]\nbind_address = \"not an address\"\n").contains("bind_address"));
+        assert!(invalid("[server]\nallowed_origins = [\"localhost:8080\"]\n").contains("origins"));
+        assert!(invalid("[server]\nallowed_origins = [\"http://a/\"]\n").contains("origins"));
+        assert!(invalid("[log]\nlevel = \"loud\"\n").contains("log.level"));
+        assert!(invalid("[storage]\npath = \"Cargo.toml\"\n").contains("storage.path"));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let prefix = "SERVER_CONFIG_TEST_MALFORMED";
+        assert!(matches!(
+            from_toml("[server\n", prefix),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            from_toml("[server]\nport = \"high\"\n", prefix),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            from_toml("[server]\nport = 70000\n", prefix),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn given_file_must_exist() {
+        let path = std::env::temp_dir().join(format!("server_config-{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let result = Config::read(Some(path.clone()), "SERVER_CONFIG_TEST_READ", 3000);
+        assert!(matches!(result, Err(ConfigError::Read(p, _)) if p == path));
+
+        std::fs::write(&path, "[server]\nport = 8082\n").unwrap();
+        let result = Config::read(Some(path.clone()), "SERVER_CONFIG_TEST_READ", 3000);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(result.unwrap().server.port, 8082);
+    }
+}
@@ -0,0 +1,116 @@
+mod metrics;
+mod middleware;
+
+use std::io::Write;
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse};
+use log::kv::{Key, Value, VisitSource};
+use serde_json::{json, Map};
+
+use metrics::Metrics;
+pub use middleware::RequestTelemetry;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+struct JsonFields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            json!(v)
+        } else if let Some(v) = value.to_i64() {
+            json!(v)
+        } else if let Some(v) = value.to_f64() {
+            json!(v)
+        } else if let Some(v) = value.to_bool() {
+            json!(v)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+// Writes every log record as a line of JSON, including the id of the request
+// being served, if any, and the key-value pairs of the record.
+pub fn init_logging(level: &str) {
+    env_logger::Builder::new()
+        .parse_filters(level)
+        .format(|buf, record| {
+            let mut fields = Map::new();
+            fields.insert("ts".to_string(), json!(buf.timestamp_micros().to_string()));
+            fields.insert("level".to_string(), json!(record.level().as_str()));
+            fields.insert("target".to_string(), json!(record.target()));
+            if let Ok(request_id) = REQUEST_ID.try_with(|id| id.clone()) {
+                fields.insert("request_id".to_string(), json!(request_id));
+            }
+            fields.insert("msg".to_string(), json!(record.args().to_string()));
+            let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+            writeln!(buf, "{}", serde_json::Value::Object(fields))
+        })
+        .init();
+}
+
+type ReadinessCheck = dyn Fn() -> Result<(), String> + Send + Sync;
+
+// Request metrics and health endpoints shared by the servers.
+#[derive(Clone)]
+pub struct Telemetry {
+    metrics: Arc<Metrics>,
+    readiness: Arc<ReadinessCheck>,
+}
+
+impl Telemetry {
+    // `readiness` checks whether the storage backend can serve requests,
+    // returning the reason if it cannot.
+    pub fn new(readiness: impl Fn() -> Result<(), String> + Send + Sync + 'static) -> Telemetry {
+        Telemetry {
+            metrics: Arc::new(Metrics::default()),
+            readiness: Arc::new(readiness),
+        }
+    }
+
+    pub fn middleware(&self) -> RequestTelemetry {
+        RequestTelemetry {
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    // Registers the `/metrics`, `/healthz` and `/readyz` resources.
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(web::Data::new(self.clone()))
+            .service(web::resource("/metrics").route(web::get().to(metrics)))
+            .service(web::resource("/healthz").route(web::get().to(healthz)))
+            .service(web::resource("/readyz").route(web::get().to(readyz)));
+    }
+}
+
+async fn metrics(telemetry: web::Data<Telemetry>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(telemetry.metrics.render())
+}
+
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+// The check may do blocking I/O, so it is run on the thread pool.
+async fn readyz(telemetry: web::Data<Telemetry>) -> HttpResponse {
+    let readiness = telemetry.readiness.clone();
+    let result = web::block(move || readiness())
+        .await
+        .unwrap_or_else(|e| Err(format!("the readiness check failed: {}", e)));
+    match result {
+        Ok(()) => HttpResponse::Ok().json(json!({ "status": "ready" })),
+        Err(reason) => {
+            log::warn!(reason = reason.as_str(); "not ready");
+            HttpResponse::ServiceUnavailable()
+                .json(json!({ "status": "unavailable", "reason": reason }))
+        }
+    }
+}
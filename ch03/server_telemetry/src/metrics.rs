@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Default)]
+struct Histogram {
+    // Non-cumulative counts, one per bucket.
+    counts: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    // Keyed by method, route and status code.
+    requests: BTreeMap<(String, String, u16), u64>,
+    // Keyed by method and route.
+    latencies: BTreeMap<(String, String), Histogram>,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub fn observe(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let registry = &mut *self.registry.lock().unwrap();
+        *registry
+            .requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+
+        let seconds = latency.as_secs_f64();
+        let histogram = registry
+            .latencies
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        if let Some(n) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            histogram.counts[n] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    // Formats the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = &*self.registry.lock().unwrap();
+        let mut text = String::new();
+
+        text.push_str("# HELP http_requests_total Number of HTTP requests processed.\n");
+        text.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in &registry.requests {
+            writeln!(
+                text,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            )
+            .unwrap();
+        }
+
+        text.push_str("# HELP http_request_duration_seconds Latency of HTTP requests.\n");
+        text.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in &registry.latencies {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.counts) {
+                cumulative += count;
+                writeln!(
+                    text,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                text,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
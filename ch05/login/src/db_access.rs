@@ -1,27 +1,28 @@
+use chrono::Utc;
+use persons_api::models::{DbPrivilege, InsertingPerson, Person, UpdatingPerson, User};
 use web_sys::wasm_bindgen::JsValue;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Person {
-    pub id: u32,
-    pub name: String,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct InsertingPerson {
-    pub name: String,
-}
-
-#[derive(Debug, Clone, PartialEq, Copy)]
-pub enum DbPriviledge {
-    CanRead,
-    CanWrite,
+// Persons are created here like the persons_db server does.
+fn new_person(id: u32, person: InsertingPerson) -> Person {
+    let now = Utc::now();
+    Person {
+        id,
+        name: person.name,
+        email: person.email,
+        phone: person.phone,
+        birth_date: person.birth_date,
+        tags: person.tags,
+        created_at: now,
+        updated_at: now,
+        version: 1,
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct User {
-    pub username: String,
-    pub password: String,
-    pub priviledge: Vec<DbPriviledge>,
+fn named(name: &str) -> InsertingPerson {
+    InsertingPerson {
+        name: name.to_string(),
+        ..InsertingPerson::default()
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -37,31 +38,19 @@ impl DbConnection {
                 User {
                     username: "joe".to_string(),
                     password: "xjoe".to_string(),
-                    priviledge: vec![DbPriviledge::CanRead],
+                    privileges: vec![DbPrivilege::CanRead],
                 },
                 User {
                     username: "susan".to_string(),
                     password: "xsusan".to_string(),
-                    priviledge: vec![DbPriviledge::CanRead, DbPriviledge::CanWrite],
+                    privileges: vec![DbPrivilege::CanRead, DbPrivilege::CanWrite],
                 },
             ],
             persons: vec![
-                Person {
-                    id: 1,
-                    name: "alice".to_string(),
-                },
-                Person {
-                    id: 2,
-                    name: "bob".to_string(),
-                },
-                Person {
-                    id: 3,
-                    name: "aaa".to_string(),
-                },
-                Person {
-                    id: 4,
-                    name: "aaaaa".to_string(),
-                },
+                new_person(1, named("alice")),
+                new_person(2, named("bob")),
+                new_person(3, named("aaa")),
+                new_person(4, named("aaaaa")),
             ],
         }
     }
@@ -105,21 +94,18 @@ impl DbConnection {
         } else {
             self.persons[self.persons.len() - 1].id + 1
         };
-        self.persons.push(Person {
-            id: new_id,
-            name: person.name,
-        });
+        self.persons.push(new_person(new_id, person));
         new_id
     }
 
-    pub fn update_person(&mut self, person: Person) -> bool {
-        if let Some((i, _)) = self
-            .persons
-            .iter()
-            .enumerate()
-            .find(|(_, p)| p.id == person.id)
-        {
-            self.persons[i] = person;
+    pub fn update_person(&mut self, person: UpdatingPerson) -> bool {
+        if let Some(current) = self.persons.iter_mut().find(|p| p.id == person.id) {
+            *current = Person {
+                updated_at: Utc::now(),
+                version: current.version + 1,
+                created_at: current.created_at,
+                ..new_person(person.id, person.person)
+            };
             true
         } else {
             false
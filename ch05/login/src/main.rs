@@ -1,9 +1,14 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use persons_ui::routes::{
+    use_login_query, use_persons_query, PersonsQuery, RedirectToLogin, Route,
+};
+use persons_ui::session::{use_session_expiry, use_stored_session, StoredSession, EXPIRED_MESSAGE};
 use yew::prelude::*;
+use yew_router::prelude::*;
 
-use crate::db_access::{DbConnection, Person};
+use crate::db_access::DbConnection;
 use crate::login::{FormValues, LoginForm};
 use crate::one_person::OnePerson;
 use crate::persons_list::PersonsList;
@@ -13,29 +18,93 @@ mod login;
 mod one_person;
 mod persons_list;
 
-enum Page {
-    Login,
-    PersonsList,
-    OnePerson,
-}
+// Distinct for each app, as they can be served by the same site.
+const SESSION_STORAGE_KEY: &str = "login.session";
 
 #[function_component]
 fn App() -> Html {
+    html! {
+        <BrowserRouter>
+            <Content />
+        </BrowserRouter>
+    }
+}
+
+#[function_component]
+fn Content() -> Html {
+    let navigator = use_navigator().unwrap();
+    let login_query = use_login_query();
+    // The filter of the list of persons, carried along by the pages of a single person.
+    let persons_query = use_persons_query();
     let db_connection = use_state(|| Rc::new(RefCell::new(DbConnection::new())));
-    let current_user: UseStateHandle<Option<String>> = use_state(|| None);
-    let page = use_state(|| Page::Login);
-    let op: UseStateHandle<Option<Person>> = use_state(|| None);
+    // The users are in the same process, so the user name is enough to identify the session.
+    let stored_session = use_stored_session::<String>(SESSION_STORAGE_KEY);
+    // Restored from the stored session, if still valid.
+    let current_user: UseStateHandle<Option<String>> = use_state(|| {
+        (*stored_session)
+            .clone()
+            .filter(|session| !session.is_expired())
+            .filter(|session| {
+                (*db_connection)
+                    .borrow()
+                    .get_user_by_username(&session.id)
+                    .is_some()
+            })
+            .map(|session| session.id)
+    });
+
+    // Discards the stored session if it has not been restored.
+    {
+        let stored_session = stored_session.clone();
+        let restored = current_user.is_some();
+        use_effect_with((), move |_| {
+            if !restored && stored_session.is_some() {
+                stored_session.delete();
+            }
+        });
+    }
 
-    let on_change_user = {
-        let page = page.clone();
+    let log_out = {
+        let current_user = current_user.clone();
+        let stored_session = stored_session.clone();
+        move || {
+            current_user.set(None);
+            stored_session.delete();
+        }
+    };
+
+    // When the session expires, the guards of the pages send to the login page.
+    use_session_expiry(
+        stored_session.clone(),
+        current_user.is_some(),
+        Callback::from({
+            let log_out = log_out.clone();
+            move |_| {
+                log_out();
+                web_sys::window()
+                    .unwrap()
+                    .alert_with_message(EXPIRED_MESSAGE)
+                    .expect("should alert expired session");
+            }
+        }),
+        Callback::from({
+            let log_out = log_out.clone();
+            move |_| log_out()
+        }),
+    );
+
+    let on_log_out = {
+        let navigator = navigator.clone();
         move |_| {
-            page.set(Page::Login);
+            log_out();
+            navigator.push(&Route::Login);
         }
     };
 
     let on_login = {
         let current_user = current_user.clone();
-        let page = page.clone();
+        let stored_session = stored_session.clone();
+        let navigator = navigator.clone();
         let db_connection = db_connection.clone();
         move |data: FormValues| {
             if let Some(user) = (*db_connection)
@@ -43,8 +112,10 @@ fn App() -> Html {
                 .get_user_by_username(&data.username)
             {
                 if user.password == data.password {
+                    stored_session.set(StoredSession::new(user.username.clone()));
                     current_user.set(Some(user.username.clone()));
-                    page.set(Page::PersonsList);
+                    let (route, query) = login_query.target();
+                    let _ = navigator.push_with_query(&route, &query);
                 } else {
                     web_sys::window()
                         .unwrap()
@@ -60,28 +131,75 @@ fn App() -> Html {
         }
     };
 
+    let on_query = Callback::from({
+        let navigator = navigator.clone();
+        move |query: PersonsQuery| {
+            let _ = navigator.push_with_query(&Route::Persons, &query);
+        }
+    });
+
     let on_one_person = Callback::from({
-        let page = page.clone();
-        let op = op.clone();
-        let db_connection = db_connection.clone();
+        let navigator = navigator.clone();
+        let persons_query = persons_query.clone();
         move |v: Option<u32>| {
-            op.set(match v {
-                Some(i) => (*db_connection).borrow_mut().get_person_by_id(i).cloned(),
-                _ => None,
-            });
-            page.set(Page::OnePerson);
+            let route = match v {
+                Some(id) => Route::EditPerson { id },
+                None => Route::NewPerson,
+            };
+            let _ = navigator.push_with_query(&route, &persons_query);
         }
     });
 
     let on_back = Callback::from({
-        let page = page.clone();
-        let op = op.clone();
-        move |_| {
-            op.set(None);
-            page.set(Page::PersonsList);
-        }
+        let on_query = on_query.clone();
+        let persons_query = persons_query.clone();
+        move |_| on_query.emit(persons_query.clone())
     });
 
+    let render = {
+        let logged_in = current_user.is_some();
+        let db_connection = (*db_connection).clone();
+        let on_login = Callback::from(on_login);
+        Callback::from(move |route: Route| match route {
+            Route::Login => html! {
+                <LoginForm on_login={on_login.clone()} />
+            },
+            Route::NotFound => html! {
+                <p>{ "Page not found." }</p>
+            },
+            _ if !logged_in => html! {
+                <RedirectToLogin />
+            },
+            Route::Home => html! {
+                <Redirect<Route> to={Route::Persons} />
+            },
+            Route::Persons => html! {
+                <PersonsList
+                    query={persons_query.clone()}
+                    on_query={on_query.clone()}
+                    on_one_person={on_one_person.clone()}
+                    db_connection={db_connection.clone()}
+                />
+            },
+            // Keyed, so that the form is not reused for another person.
+            Route::NewPerson => html! {
+                <OnePerson key="new" op={None} db_connection={db_connection.clone()} on_back={on_back.clone()} />
+            },
+            Route::EditPerson { id } => {
+                let op = db_connection.borrow().get_person_by_id(id).cloned();
+                if op.is_some() {
+                    html! {
+                        <OnePerson key={id.to_string()} {op} db_connection={db_connection.clone()} on_back={on_back.clone()} />
+                    }
+                } else {
+                    html! {
+                        <p>{ format!("Person {} not found.", id) }</p>
+                    }
+                }
+            }
+        })
+    };
+
     html! {
         <div>
             <style>
@@ -102,33 +220,16 @@ fn App() -> Html {
                         }
                     }
                     </span>
-                    {
-                        match &(*page) {
-                            Page::Login => html! { <div/> },
-                            _ => html! {
-                                <span>
-                                    { " " }
-                                    <button onclick={on_change_user}>{ "Change User" }</button>
-                                </span>
-                            }
-                        }
+                    if current_user.is_some() {
+                        <span>
+                            { " " }
+                            <button onclick={on_log_out}>{ "Log Out" }</button>
+                        </span>
                     }
                 </p>
                 <hr/>
             </header>
-            {
-                match &(*page) {
-                    Page::Login => html! {
-                        <LoginForm {on_login} />
-                    },
-                    Page::PersonsList => html! {
-                        <PersonsList {on_one_person} db_connection={(*db_connection).clone()} />
-                    },
-                    Page::OnePerson => html! {
-                        <OnePerson op={(*op).clone()} db_connection={(*db_connection).clone()} on_back={on_back} />
-                    }
-                }
-            }
+            <Switch<Route> {render} />
             <footer>
                 <hr/>
                 { "\u{A9} Carlo Milanesi - Developed using Yew" }
@@ -1,12 +1,12 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use persons_api::models::{InsertingPerson, Person, UpdatingPerson};
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
 use yew::Properties;
 
-use crate::db_access::InsertingPerson;
-use crate::db_access::{DbConnection, Person};
+use crate::db_access::DbConnection;
 
 #[derive(PartialEq, Clone, Properties)]
 pub struct OnePersonProps {
@@ -49,6 +49,7 @@ pub fn OnePerson(props: &OnePersonProps) -> Html {
         Callback::from(move |_| {
             db_connection.borrow_mut().insert_person(InsertingPerson {
                 name: (*name).to_string(),
+                ..InsertingPerson::default()
             });
             on_back.emit(());
         })
@@ -59,9 +60,12 @@ pub fn OnePerson(props: &OnePersonProps) -> Html {
         let db_connection = db_connection.clone();
         let on_back = on_back.clone();
         Callback::from(move |_| {
-            db_connection.borrow_mut().update_person(Person {
+            db_connection.borrow_mut().update_person(UpdatingPerson {
                 id: (*id).parse().unwrap(),
-                name: (*name).to_string(),
+                person: InsertingPerson {
+                    name: (*name).to_string(),
+                    ..InsertingPerson::default()
+                },
             });
             on_back.emit(());
         })
@@ -89,7 +93,7 @@ pub fn OnePerson(props: &OnePersonProps) -> Html {
                     onclick={if is_inserting { on_insert } else { on_update } }
                 >{ if is_inserting { "Insert" } else { "Update" } }</button>
                 { " " }
-                <button>{ "Cancel" }</button>
+                <button onclick={move |_| on_back.emit(())}>{ "Cancel" }</button>
             </div>
         </div>
     }
@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::collections::HashSet;
 use std::rc::Rc;
 
+use persons_ui::routes::PersonsQuery;
 use web_sys::wasm_bindgen::JsValue;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
@@ -12,6 +13,9 @@ use crate::db_access::DbConnection;
 
 #[derive(PartialEq, Clone, Properties)]
 pub struct Props {
+    // The filter of the shown persons, taken from the URL.
+    pub query: PersonsQuery,
+    pub on_query: Callback<PersonsQuery>,
     pub on_one_person: Callback<Option<u32>>,
     pub db_connection: Rc<RefCell<DbConnection>>,
 }
@@ -20,12 +24,50 @@ pub struct Props {
 pub fn PersonsList(props: &Props) -> Html {
     let db_connection = props.db_connection.clone();
     let on_one_person = props.on_one_person.clone();
+    let on_query = props.on_query.clone();
 
-    let filtered_persons = use_state(|| db_connection.borrow_mut().get_persons_by_partial_name(""));
+    let filtered_persons = use_state(Vec::new);
     let id_to_find: UseStateHandle<Option<u32>> = use_state(|| None);
     let name_portion = use_state(|| AttrValue::from(""));
     let selected_ids = use_set(HashSet::<u32>::new());
 
+    // Also run when the URL is changed by the browser history.
+    {
+        let filtered_persons = filtered_persons.clone();
+        let id_to_find = id_to_find.clone();
+        let name_portion = name_portion.clone();
+        let selected_ids = selected_ids.clone();
+        let db_connection = db_connection.clone();
+        use_effect_with(props.query.clone(), move |query| {
+            let db_connection = db_connection.borrow();
+            filtered_persons.set(match query.id {
+                Some(id) => db_connection
+                    .get_person_by_id(id)
+                    .cloned()
+                    .into_iter()
+                    .collect(),
+                None => db_connection
+                    .get_persons_by_partial_name(query.partial_name.as_deref().unwrap_or_default()),
+            });
+            id_to_find.set(query.id);
+            name_portion.set(query.partial_name.clone().unwrap_or_default().into());
+            selected_ids.clear();
+        });
+    }
+
+    let on_change_id = {
+        let id_to_find = id_to_find.clone();
+        move |e: Event| {
+            id_to_find.set(
+                e.target_unchecked_into::<HtmlInputElement>()
+                    .value()
+                    .trim()
+                    .parse()
+                    .ok(),
+            );
+        }
+    };
+
     let on_change_name_portion = {
         let name_portion = name_portion.clone();
         move |e: Event| {
@@ -34,32 +76,24 @@ pub fn PersonsList(props: &Props) -> Html {
     };
 
     let on_find = {
-        let filtered_persons = filtered_persons.clone();
         let id_to_find = id_to_find.clone();
-        let db_connection = db_connection.clone();
+        let on_query = on_query.clone();
         move |_| {
-            filtered_persons.set(
-                if let Some(p) = (*id_to_find)
-                    .and_then(|id| db_connection.borrow_mut().get_person_by_id(id).cloned())
-                {
-                    vec![p.clone()]
-                } else {
-                    vec![]
-                },
-            )
+            on_query.emit(PersonsQuery {
+                partial_name: None,
+                id: *id_to_find,
+            })
         }
     };
 
     let on_filter = {
-        let filtered_persons = filtered_persons.clone();
         let name_portion = name_portion.clone();
-        let db_connection = db_connection.clone();
+        let on_query = on_query.clone();
         move |_| {
-            filtered_persons.set(
-                db_connection
-                    .borrow_mut()
-                    .get_persons_by_partial_name(&name_portion),
-            )
+            on_query.emit(PersonsQuery {
+                partial_name: Some(name_portion.to_string()).filter(|portion| !portion.is_empty()),
+                id: None,
+            })
         }
     };
 
@@ -93,12 +127,15 @@ pub fn PersonsList(props: &Props) -> Html {
                 <label>{ "Id: " }</label>
                 <input
                     type="number"
+                    value={id_to_find.map(|id| id.to_string()).unwrap_or_default()}
+                    onchange={on_change_id}
                 />
                 <button onclick={on_find}>{ "Find" }</button>
             </div>
             <div>
                 <label>{ "Name portion: " }</label>
                 <input
+                    value={(*name_portion).clone()}
                     onchange={on_change_name_portion}
                 />
                 { " " }
@@ -0,0 +1,368 @@
+use std::fmt;
+
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+
+use crate::models::{
+    DeleteReport, FieldError, Format, ImportReport, InsertingPerson, Person, PersonPatch,
+    ProblemDetails, Session, UpdatingPerson,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    // The server could not be reached.
+    Network(String),
+    // The server answered with something else than what was expected.
+    Decode(String),
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(Vec<FieldError>),
+    Invalid(Vec<FieldError>),
+    // The person has been changed since the version the request was based on.
+    PreconditionFailed { current_version: Option<u32> },
+    PreconditionRequired,
+    TooManyRequests { retry_after: Option<u64> },
+    // Any other error status, with the detail given by the server.
+    Status(u16, String),
+}
+
+fn write_field_errors(f: &mut fmt::Formatter<'_>, errors: &[FieldError]) -> fmt::Result {
+    for (i, e) in errors.iter().enumerate() {
+        write!(
+            f,
+            "{}{}: {}",
+            if i == 0 { " (" } else { "; " },
+            e.field,
+            e.message
+        )?;
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Network(msg) => write!(f, "Request failed: {}", msg),
+            ClientError::Decode(msg) => write!(f, "Unexpected response: {}", msg),
+            ClientError::BadRequest(msg)
+            | ClientError::Unauthorized(msg)
+            | ClientError::Forbidden(msg)
+            | ClientError::NotFound(msg)
+            | ClientError::Status(_, msg) => write!(f, "{}", msg),
+            ClientError::Conflict(errors) => {
+                write!(f, "The data conflicts with existing persons.")?;
+                write_field_errors(f, errors)
+            }
+            ClientError::Invalid(errors) => {
+                write!(f, "The data is not valid.")?;
+                write_field_errors(f, errors)
+            }
+            ClientError::PreconditionFailed {
+                current_version: Some(version),
+            } => write!(
+                f,
+                "The person has been modified, its current version is {}.",
+                version
+            ),
+            ClientError::PreconditionFailed {
+                current_version: None,
+            } => write!(f, "The person has been modified."),
+            ClientError::PreconditionRequired => write!(f, "Missing If-Match header."),
+            ClientError::TooManyRequests {
+                retry_after: Some(seconds),
+            } => write!(f, "Too many requests, retry after {} seconds.", seconds),
+            ClientError::TooManyRequests { retry_after: None } => write!(f, "Too many requests."),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> ClientError {
+        if error.is_decode() {
+            ClientError::Decode(error.to_string())
+        } else {
+            ClientError::Network(error.to_string())
+        }
+    }
+}
+
+// Used only to log in, as the following requests send the token of the session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Auth {
+    Password(Credentials),
+    Token(String),
+}
+
+// A client of the REST API of persons_db, sending the given credentials with every request.
+// The event stream is not read, as browsers are better served by an `EventSource`
+// opened on `events_url`.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    auth: Option<Auth>,
+}
+
+// Clients are equal when they talk to the same server in the same session,
+// so that they can be passed as properties to components.
+impl PartialEq for Client {
+    fn eq(&self, other: &Client) -> bool {
+        self.base_url == other.base_url && self.auth == other.auth
+    }
+}
+
+fn version_in(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get(header::ETAG)?
+        .to_str()
+        .ok()?
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .ok()
+}
+
+async fn error_from(response: Response) -> ClientError {
+    let status = response.status();
+    let current_version = version_in(response.headers());
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let (detail, errors) = match response.json::<ProblemDetails>().await {
+        Ok(problem) => (problem.detail, problem.errors),
+        Err(_) => (
+            status.canonical_reason().unwrap_or_default().to_string(),
+            vec![],
+        ),
+    };
+    match status {
+        StatusCode::BAD_REQUEST => ClientError::BadRequest(detail),
+        StatusCode::UNAUTHORIZED => ClientError::Unauthorized(detail),
+        StatusCode::FORBIDDEN => ClientError::Forbidden(detail),
+        StatusCode::NOT_FOUND => ClientError::NotFound(detail),
+        StatusCode::CONFLICT => ClientError::Conflict(errors),
+        StatusCode::UNPROCESSABLE_ENTITY => ClientError::Invalid(errors),
+        StatusCode::PRECONDITION_FAILED => ClientError::PreconditionFailed { current_version },
+        StatusCode::PRECONDITION_REQUIRED => ClientError::PreconditionRequired,
+        StatusCode::TOO_MANY_REQUESTS => ClientError::TooManyRequests { retry_after },
+        _ => ClientError::Status(status.as_u16(), detail),
+    }
+}
+
+async fn send(builder: RequestBuilder) -> Result<Response, ClientError> {
+    let response = builder.send().await?;
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(error_from(response).await)
+    }
+}
+
+async fn send_json<T: DeserializeOwned>(builder: RequestBuilder) -> Result<T, ClientError> {
+    Ok(send(builder).await?.json::<T>().await?)
+}
+
+// The new version of a changed person is only given by the ETag header.
+async fn send_versioned(builder: RequestBuilder) -> Result<u32, ClientError> {
+    let response = send(builder).await?;
+    version_in(response.headers())
+        .ok_or_else(|| ClientError::Decode("missing or invalid ETag header".to_string()))
+}
+
+impl Client {
+    // `base_url` is the address of the server, without a trailing slash.
+    pub fn new(base_url: &str) -> Client {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth: None,
+        }
+    }
+
+    // To log in, by `authenticate`.
+    pub fn with_credentials(self, credentials: Credentials) -> Client {
+        Client {
+            auth: Some(Auth::Password(credentials)),
+            ..self
+        }
+    }
+
+    // To send the requests in the session with the given token.
+    pub fn with_token(self, token: String) -> Client {
+        Client {
+            auth: Some(Auth::Token(token)),
+            ..self
+        }
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        match &self.auth {
+            Some(Auth::Token(token)) => Some(token),
+            _ => None,
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.auth {
+            Some(Auth::Password(c)) => builder.basic_auth(&c.username, Some(&c.password)),
+            Some(Auth::Token(token)) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    // The address of the stream of the changes to the persons, after the event `since`.
+    // It carries the token of the session, as an `EventSource` cannot send headers,
+    // so it is given only to a client logged in a session.
+    pub fn events_url(&self, since: Option<u64>) -> Option<String> {
+        let mut url = Url::parse(&format!("{}/persons/events", self.base_url)).ok()?;
+        url.query_pairs_mut()
+            .append_pair("access_token", self.token()?);
+        if let Some(since) = since {
+            url.query_pairs_mut()
+                .append_pair("since", &since.to_string());
+        }
+        Some(url.into())
+    }
+
+    fn request_if_match(&self, method: Method, path: &str, version: u32) -> RequestBuilder {
+        self.request(method, path)
+            .header(header::IF_MATCH, format!("\"{}\"", version))
+    }
+
+    // With the credentials, opens a new session; with a token, gives back its session.
+    pub async fn authenticate(&self) -> Result<Session, ClientError> {
+        send_json(self.request(Method::GET, "/authenticate")).await
+    }
+
+    // Closes the session, so that its token can no longer be used.
+    pub async fn log_out(&self) -> Result<(), ClientError> {
+        send(self.request(Method::POST, "/logout")).await?;
+        Ok(())
+    }
+
+    pub async fn get_person(&self, id: u32) -> Result<Person, ClientError> {
+        send_json(self.request(Method::GET, &format!("/person/{}", id))).await
+    }
+
+    pub async fn get_persons(&self, partial_name: &str) -> Result<Vec<Person>, ClientError> {
+        send_json(
+            self.request(Method::GET, "/persons")
+                .query(&[("partial_name", partial_name)]),
+        )
+        .await
+    }
+
+    pub async fn export_persons(&self, format: Format) -> Result<String, ClientError> {
+        Ok(send(
+            self.request(Method::GET, "/persons/export")
+                .query(&[("format", format)]),
+        )
+        .await?
+        .text()
+        .await?)
+    }
+
+    // A rejected atomic import is not an error, as its report tells which rows are invalid.
+    pub async fn import_persons(
+        &self,
+        format: Format,
+        atomic: bool,
+        body: String,
+    ) -> Result<ImportReport, ClientError> {
+        let response = self
+            .request(Method::POST, "/persons/import")
+            .query(&[("format", format)])
+            .query(&[("atomic", atomic)])
+            .header(
+                header::CONTENT_TYPE,
+                match format {
+                    Format::Csv => "text/csv",
+                    Format::Json => "application/json",
+                },
+            )
+            .body(body)
+            .send()
+            .await?;
+        if response.status().is_success()
+            || (atomic && response.status() == StatusCode::UNPROCESSABLE_ENTITY)
+        {
+            Ok(response.json::<ImportReport>().await?)
+        } else {
+            Err(error_from(response).await)
+        }
+    }
+
+    // Deletes the persons with the given ids, if they still have the given versions.
+    // The report tells which ones have been deleted.
+    pub async fn delete_persons(
+        &self,
+        versions: &[(u32, u32)],
+    ) -> Result<DeleteReport, ClientError> {
+        let id_list = versions
+            .iter()
+            .map(|(id, version)| format!("{}:{}", id, version))
+            .collect::<Vec<_>>()
+            .join(",");
+        send_json(
+            self.request(Method::DELETE, "/persons")
+                .query(&[("id_list", id_list)]),
+        )
+        .await
+    }
+
+    pub async fn delete_person(&self, id: u32, version: u32) -> Result<(), ClientError> {
+        send(self.request_if_match(Method::DELETE, &format!("/person/{}", id), version)).await?;
+        Ok(())
+    }
+
+    // Returns the id of the new person.
+    pub async fn insert_person(&self, person: &InsertingPerson) -> Result<u32, ClientError> {
+        send_json(self.request(Method::POST, "/one_person").json(person)).await
+    }
+
+    // Returns the new version of the person.
+    pub async fn update_person(
+        &self,
+        person: &UpdatingPerson,
+        version: u32,
+    ) -> Result<u32, ClientError> {
+        send_versioned(
+            self.request_if_match(Method::PUT, "/one_person", version)
+                .json(person),
+        )
+        .await
+    }
+
+    // Returns the new version of the person.
+    pub async fn patch_person(
+        &self,
+        patch: &PersonPatch,
+        version: u32,
+    ) -> Result<u32, ClientError> {
+        send_versioned(
+            self.request_if_match(Method::PATCH, "/one_person", version)
+                .json(patch),
+        )
+        .await
+    }
+}
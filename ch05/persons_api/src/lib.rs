@@ -0,0 +1,6 @@
+// The types exchanged by the persons_db server and its clients,
+// and a typed client of its REST API.
+pub mod models;
+
+#[cfg(feature = "client")]
+pub mod client;
@@ -1,38 +1,26 @@
-use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Person {
-    pub id: u32,
-    pub name: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct InsertingPerson {
-    pub name: String,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
-pub enum DbPrivilege {
-    CanRead,
-    CanWrite,
-}
+use chrono::Utc;
+use persons_api::models::{
+    ChangeKind, DbPrivilege, InsertingPerson, Person, PersonPatch, UpdatingPerson, User,
+};
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
-pub struct User {
-    pub username: String,
-    pub password: String,
-    pub privileges: Vec<DbPrivilege>,
-}
+use crate::events::{EventLog, Subscription};
+use crate::validation::{self, FieldError};
 
 pub struct DbConnection {
-    persons: Vec<Person>,
+    persons: BTreeMap<u32, Person>,
+    next_id: u32,
     users: Vec<User>,
+    events: EventLog,
 }
 
 impl DbConnection {
     pub fn new() -> DbConnection {
         DbConnection {
-            persons: vec![],
+            persons: BTreeMap::new(),
+            next_id: 1,
+            events: EventLog::new(),
             users: vec![
                 User {
                     username: "joe".to_string(),
@@ -48,56 +36,167 @@ impl DbConnection {
         }
     }
 
+    pub fn subscribe(&mut self, since: Option<u64>) -> Subscription {
+        self.events.subscribe(since)
+    }
+
     pub fn get_user_by_username(&self, username: &str) -> Option<User> {
         Some(self.users.iter().find(|u| u.username == username)?.clone())
     }
 
     pub fn get_person_by_id(&self, id: u32) -> Option<Person> {
-        Some(self.persons.iter().find(|p| p.id == id)?.clone())
+        self.persons.get(&id).cloned()
+    }
+
+    pub fn get_all_persons(&self) -> Vec<Person> {
+        self.persons.values().cloned().collect()
     }
 
     pub fn get_persons_by_partial_name<'a>(
         &'a self,
         subname: &'a str,
-    ) -> impl Iterator<Item = &Person> + 'a {
+    ) -> impl Iterator<Item = &'a Person> + 'a {
         self.persons
-            .iter()
+            .values()
             .filter(move |p| p.name.contains(subname))
     }
 
     pub fn delete_by_id(&mut self, id: u32) -> bool {
-        if let Some((n, _)) = self.persons.iter().enumerate().find(|(_, p)| p.id == id) {
-            self.persons.remove(n);
+        if self.persons.remove(&id).is_some() {
+            self.events.publish(ChangeKind::Delete, id, None);
             true
         } else {
             false
         }
     }
 
-    pub fn insert_person(&mut self, person: InsertingPerson) -> u32 {
-        let new_id = if self.persons.is_empty() {
-            1
+    pub fn insert_person(&mut self, person: InsertingPerson) -> Result<u32, Vec<FieldError>> {
+        let new_id = self.push_person(person)?;
+        self.publish_insert(new_id);
+        Ok(new_id)
+    }
+
+    // Inserts all the given persons, reporting the outcome of each one.
+    // If `atomic` is true and any of them is invalid, none is inserted.
+    pub fn import_persons(
+        &mut self,
+        persons: Vec<InsertingPerson>,
+        atomic: bool,
+    ) -> Vec<Result<u32, Vec<FieldError>>> {
+        let saved_persons = atomic.then(|| self.persons.clone());
+        let results = persons
+            .into_iter()
+            .map(|p| self.push_person(p))
+            .collect::<Vec<_>>();
+        if let Some(saved_persons) = saved_persons {
+            if results.iter().any(Result::is_err) {
+                self.persons = saved_persons;
+                return results;
+            }
+        }
+        for id in results.iter().flatten() {
+            self.publish_insert(*id);
+        }
+        results
+    }
+
+    fn push_person(&mut self, person: InsertingPerson) -> Result<u32, Vec<FieldError>> {
+        let errors = self.validate(None, &person);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let new_id = self.next_id;
+        self.next_id += 1;
+        let now = Utc::now();
+        self.persons.insert(
+            new_id,
+            Person {
+                id: new_id,
+                name: person.name,
+                email: person.email,
+                phone: person.phone,
+                birth_date: person.birth_date,
+                tags: person.tags,
+                created_at: now,
+                updated_at: now,
+                version: 1,
+            },
+        );
+        Ok(new_id)
+    }
+
+    fn publish_insert(&mut self, id: u32) {
+        let inserted = self.get_person_by_id(id);
+        self.events.publish(ChangeKind::Insert, id, inserted);
+    }
+
+    pub fn update_person(
+        &mut self,
+        person: UpdatingPerson,
+    ) -> Result<Option<u32>, Vec<FieldError>> {
+        if self.persons.contains_key(&person.id) {
+            self.replace_fields(person.id, person.person).map(Some)
         } else {
-            self.persons[self.persons.len() - 1].id + 1
-        };
-        self.persons.push(Person {
-            id: new_id,
-            name: person.name,
-        });
-        new_id
+            Ok(None)
+        }
     }
 
-    pub fn update_person(&mut self, person: Person) -> bool {
-        if let Some((n, _)) = self
-            .persons
-            .iter()
-            .enumerate()
-            .find(|(_, p)| p.id == person.id)
-        {
-            self.persons[n] = person;
-            true
+    pub fn patch_person(&mut self, patch: PersonPatch) -> Result<Option<u32>, Vec<FieldError>> {
+        if let Some(current) = self.persons.get(&patch.id) {
+            let person = InsertingPerson {
+                name: patch.name.unwrap_or_else(|| current.name.clone()),
+                email: patch.email.unwrap_or_else(|| current.email.clone()),
+                phone: patch.phone.unwrap_or_else(|| current.phone.clone()),
+                birth_date: patch.birth_date.unwrap_or(current.birth_date),
+                tags: patch.tags.unwrap_or_else(|| current.tags.clone()),
+            };
+            self.replace_fields(patch.id, person).map(Some)
         } else {
-            false
+            Ok(None)
+        }
+    }
+
+    // Returns the new version of the person.
+    fn replace_fields(&mut self, id: u32, person: InsertingPerson) -> Result<u32, Vec<FieldError>> {
+        let errors = self.validate(Some(id), &person);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let current = self.persons.get_mut(&id).unwrap();
+        current.name = person.name;
+        current.email = person.email;
+        current.phone = person.phone;
+        current.birth_date = person.birth_date;
+        current.tags = person.tags;
+        current.updated_at = Utc::now();
+        current.version += 1;
+        let updated = current.clone();
+        self.events
+            .publish(ChangeKind::Update, updated.id, Some(updated.clone()));
+        Ok(updated.version)
+    }
+
+    fn validate(&self, id: Option<u32>, person: &InsertingPerson) -> Vec<FieldError> {
+        let mut errors = vec![];
+        validation::validate_name(&person.name, &mut errors);
+        if let Some(email) = &person.email {
+            validation::validate_email(email, &mut errors);
+            if self.persons.values().any(|p| {
+                Some(p.id) != id
+                    && p.email
+                        .as_ref()
+                        .is_some_and(|e| e.eq_ignore_ascii_case(email))
+            }) {
+                errors.push(FieldError::conflict("email", "is already in use"));
+            }
+        }
+        if let Some(phone) = &person.phone {
+            validation::validate_phone(phone, &mut errors);
+        }
+        if let Some(birth_date) = person.birth_date {
+            validation::validate_birth_date(birth_date, &mut errors);
         }
+        validation::validate_tags(&person.tags, &mut errors);
+        errors
     }
 }
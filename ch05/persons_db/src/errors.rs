@@ -0,0 +1,112 @@
+use std::fmt;
+
+use actix_web::http::header;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use persons_api::models::{self, ProblemDetails};
+
+use crate::preconditions::etag;
+use crate::validation::FieldError;
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(Vec<FieldError>),
+    PreconditionFailed { current_version: u32 },
+    PreconditionRequired,
+    Invalid(Vec<FieldError>),
+    TooManyRequests { retry_after: u64 },
+}
+
+impl ApiError {
+    pub fn not_found(id: u32) -> ApiError {
+        ApiError::NotFound(format!("Person {} not found.", id))
+    }
+
+    fn field_errors(&self) -> Vec<models::FieldError> {
+        match self {
+            ApiError::Conflict(errors) | ApiError::Invalid(errors) => {
+                errors.iter().cloned().map(Into::into).collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+// Uniqueness violations conflict with the stored data rather than being malformed,
+// so they are reported as such when they are the only problem.
+impl From<Vec<FieldError>> for ApiError {
+    fn from(errors: Vec<FieldError>) -> ApiError {
+        if errors.iter().all(|e| e.conflict) {
+            ApiError::Conflict(errors)
+        } else {
+            ApiError::Invalid(errors)
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg) => write!(f, "{}", msg),
+            ApiError::Conflict(_) => write!(f, "The data conflicts with existing persons."),
+            ApiError::PreconditionFailed { current_version } => write!(
+                f,
+                "The person has been modified, its current version is {}.",
+                current_version
+            ),
+            ApiError::PreconditionRequired => write!(f, "Missing If-Match header."),
+            ApiError::Invalid(_) => write!(f, "The data is not valid."),
+            ApiError::TooManyRequests { retry_after } => {
+                write!(f, "Too many requests, retry after {} seconds.", retry_after)
+            }
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut builder = HttpResponse::build(status);
+        builder.content_type("application/problem+json");
+        match self {
+            ApiError::Unauthorized(_) => {
+                builder.insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"PersonsApp\""));
+            }
+            ApiError::PreconditionFailed { current_version } => {
+                builder.insert_header(etag(*current_version));
+            }
+            ApiError::TooManyRequests { retry_after } => {
+                builder.insert_header((header::RETRY_AFTER, *retry_after));
+            }
+            _ => {}
+        }
+        builder.json(ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: self.to_string(),
+            errors: self.field_errors(),
+        })
+    }
+}
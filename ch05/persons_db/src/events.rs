@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use actix_web::rt::time;
+use actix_web::web::Bytes;
+use futures::{stream, Future, Stream, StreamExt};
+use persons_api::models::{ChangeEvent, ChangeKind, Person};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+// How many past events are kept for clients that reconnect.
+const HISTORY_CAPACITY: usize = 1000;
+const KEEP_ALIVE_PERIOD: Duration = Duration::from_secs(15);
+
+fn to_sse(event: &ChangeEvent) -> Bytes {
+    let kind = match event.kind {
+        ChangeKind::Insert => "insert",
+        ChangeKind::Update => "update",
+        ChangeKind::Delete => "delete",
+    };
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.seq,
+        kind,
+        serde_json::to_string(event).unwrap()
+    ))
+}
+
+pub struct Subscription {
+    // True if some of the requested events are no longer in the history,
+    // so the client has to reload the whole list.
+    pub missed: bool,
+    pub replay: Vec<ChangeEvent>,
+    pub receiver: UnboundedReceiver<ChangeEvent>,
+}
+
+pub struct EventLog {
+    last_seq: u64,
+    history: VecDeque<ChangeEvent>,
+    subscribers: Vec<UnboundedSender<ChangeEvent>>,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog {
+            last_seq: 0,
+            history: VecDeque::new(),
+            subscribers: vec![],
+        }
+    }
+
+    pub fn publish(&mut self, kind: ChangeKind, id: u32, person: Option<Person>) {
+        self.last_seq += 1;
+        let event = ChangeEvent {
+            seq: self.last_seq,
+            kind,
+            id,
+            person,
+        };
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+        if self.history.len() == HISTORY_CAPACITY {
+            self.history.pop_front();
+        }
+        self.history.push_back(event);
+    }
+
+    // Subscribes to the events following the one numbered `since`,
+    // or only to future events if `since` is not specified.
+    pub fn subscribe(&mut self, since: Option<u64>) -> Subscription {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.push(sender);
+        let (missed, replay) = match since {
+            Some(since) => {
+                let first_kept = self.history.front().map_or(self.last_seq + 1, |e| e.seq);
+                (
+                    since > self.last_seq || since + 1 < first_kept,
+                    self.history
+                        .iter()
+                        .filter(|e| e.seq > since)
+                        .cloned()
+                        .collect(),
+                )
+            }
+            None => (false, vec![]),
+        };
+        Subscription {
+            missed,
+            replay,
+            receiver,
+        }
+    }
+}
+
+// Formats a subscription as a server-sent events stream:
+// a "reset" event if some events were missed, the replayed events, then the live ones,
+// interleaved with comments that keep idle connections open, until `closed` completes.
+pub fn event_stream(
+    subscription: Subscription,
+    closed: impl Future<Output = ()>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let mut initial = vec![];
+    if subscription.missed {
+        initial.push(Bytes::from("event: reset\ndata: {}\n\n"));
+    }
+    initial.extend(subscription.replay.iter().map(to_sse));
+
+    let live = stream::unfold(subscription.receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((to_sse(&event), receiver))
+    });
+    let keep_alive = stream::unfold(time::interval(KEEP_ALIVE_PERIOD), |mut interval| async {
+        interval.tick().await;
+        Some((Bytes::from(": keep-alive\n\n"), interval))
+    });
+
+    stream::iter(initial)
+        .chain(stream::select(live, keep_alive))
+        .take_until(closed)
+        .map(Ok)
+}
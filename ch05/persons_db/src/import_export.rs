@@ -0,0 +1,164 @@
+use actix_web::web::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::{stream, Stream, StreamExt};
+use persons_api::models::{Format, ImportReport, InsertingPerson, Person, RowResult};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::db_access::DbConnection;
+use crate::validation::FieldError;
+
+// The header row of the CSV exports, written even when there are no rows.
+const CSV_HEADER: &str = "id,name,email,phone,birth_date,tags,created_at,updated_at,version\n";
+
+// CSV cells cannot hold lists, so tags are joined by semicolons.
+// The columns that are generated by the server are ignored on import.
+// The fields are in the order of the columns of `CSV_HEADER`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvPerson {
+    id: Option<u32>,
+    name: String,
+    email: Option<String>,
+    phone: Option<String>,
+    birth_date: Option<NaiveDate>,
+    tags: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    version: Option<u32>,
+}
+
+impl From<Person> for CsvPerson {
+    fn from(p: Person) -> CsvPerson {
+        CsvPerson {
+            id: Some(p.id),
+            name: p.name,
+            email: p.email,
+            phone: p.phone,
+            birth_date: p.birth_date,
+            tags: Some(p.tags.join(";")),
+            created_at: Some(p.created_at),
+            updated_at: Some(p.updated_at),
+            version: Some(p.version),
+        }
+    }
+}
+
+impl From<CsvPerson> for InsertingPerson {
+    fn from(p: CsvPerson) -> InsertingPerson {
+        InsertingPerson {
+            name: p.name,
+            email: p.email,
+            phone: p.phone,
+            birth_date: p.birth_date,
+            tags: p
+                .tags
+                .map(|t| {
+                    t.split(';')
+                        .filter(|t| !t.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub fn export_stream(
+    persons: Vec<Person>,
+    format: Format,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (opening, closing) = match format {
+        Format::Csv => (CSV_HEADER, ""),
+        Format::Json => ("[", "]"),
+    };
+    let rows = persons
+        .into_iter()
+        .enumerate()
+        .map(move |(n, p)| match format {
+            Format::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+                writer.serialize(CsvPerson::from(p)).unwrap();
+                Bytes::from(writer.into_inner().unwrap())
+            }
+            Format::Json => {
+                let separator = if n == 0 { "" } else { "," };
+                Bytes::from(separator.to_string() + &serde_json::to_string(&p).unwrap())
+            }
+        });
+    stream::once(async move { Bytes::from(opening) })
+        .chain(stream::iter(rows))
+        .chain(stream::once(async move { Bytes::from(closing) }))
+        .map(Ok)
+}
+
+// Returns an error message if the body as a whole cannot be parsed.
+fn parse_rows(
+    body: &[u8],
+    format: Format,
+) -> Result<Vec<Result<InsertingPerson, Vec<FieldError>>>, String> {
+    match format {
+        Format::Csv => Ok(csv::Reader::from_reader(body)
+            .deserialize::<CsvPerson>()
+            .map(|row| {
+                row.map(InsertingPerson::from)
+                    .map_err(|e| vec![FieldError::new("row", &e.to_string())])
+            })
+            .collect()),
+        Format::Json => {
+            let values = serde_json::from_slice::<Vec<serde_json::Value>>(body)
+                .map_err(|e| format!("Expected a JSON array of persons: {}", e))?;
+            Ok(values
+                .into_iter()
+                .map(|value| {
+                    serde_json::from_value::<InsertingPerson>(value)
+                        .map_err(|e| vec![FieldError::new("row", &e.to_string())])
+                })
+                .collect())
+        }
+    }
+}
+
+pub fn import(
+    db_conn: &mut DbConnection,
+    body: &[u8],
+    format: Format,
+    atomic: bool,
+) -> Result<ImportReport, String> {
+    let parsed = parse_rows(body, format)?;
+    let mut rows = parsed
+        .iter()
+        .enumerate()
+        .map(|(n, p)| RowResult {
+            row: n + 1,
+            id: None,
+            errors: p
+                .as_ref()
+                .err()
+                .map(|errors| errors.iter().cloned().map(Into::into).collect())
+                .unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    if atomic && rows.iter().any(|r| !r.errors.is_empty()) {
+        return Ok(ImportReport { imported: 0, rows });
+    }
+
+    let (row_numbers, persons): (Vec<_>, Vec<_>) = parsed
+        .into_iter()
+        .enumerate()
+        .filter_map(|(n, p)| Some((n, p.ok()?)))
+        .unzip();
+    let results = db_conn.import_persons(persons, atomic);
+    let failed = results.iter().any(Result::is_err);
+    for (n, result) in row_numbers.into_iter().zip(results) {
+        match result {
+            Ok(id) if !(atomic && failed) => rows[n].id = Some(id),
+            Ok(_) => {}
+            Err(errors) => rows[n].errors = errors.into_iter().map(Into::into).collect(),
+        }
+    }
+    Ok(ImportReport {
+        imported: rows.iter().filter(|r| r.id.is_some()).count(),
+        rows,
+    })
+}
@@ -1,85 +1,139 @@
 mod db_access;
+mod errors;
+mod events;
+mod import_export;
+mod preconditions;
+mod rate_limit;
+mod sessions;
+mod validation;
 
-use std::sync::Mutex;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use actix_cors::Cors;
-use actix_web::{delete, get, post, put, web, App, HttpResponse, HttpServer, Responder};
-use actix_web_httpauth::extractors::basic::{BasicAuth, Config};
-use db_access::{DbConnection, DbPrivilege, InsertingPerson, Person};
-use serde_derive::{Deserialize, Serialize};
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{
+    delete, get, patch, post, put, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer,
+};
+use errors::ApiError;
+use futures::future::{self, FutureExt};
+use persons_api::models::{
+    DbPrivilege, DeleteOutcome, DeleteReport, DeleteResult, Format, InsertingPerson, PersonPatch,
+    Session, UpdatingPerson, User,
+};
+use preconditions::{check_if_match, etag};
+use rate_limit::{RateLimitSettings, RateLimiter};
+use serde_derive::Deserialize;
+use server_config::Config as ServerConfig;
+use server_telemetry::Telemetry;
+use sessions::{Auth, SessionSettings, Sessions};
+
+// Maximum size of the files accepted by the import endpoint.
+const MAX_IMPORT_SIZE: usize = 16 * 1024 * 1024;
 
 struct AppState {
-    db: Mutex<db_access::DbConnection>,
+    // Requests only reading the database can be served concurrently.
+    db: RwLock<db_access::DbConnection>,
+    rate_limiter: Arc<RateLimiter>,
+    sessions: Arc<Sessions>,
 }
 
 fn check_credentials(
-    auth: BasicAuth,
+    auth: Auth,
     data: &web::Data<AppState>,
     required_privilege: DbPrivilege,
-) -> Result<Vec<DbPrivilege>, String> {
-    let db_conn = &data.db.lock().unwrap();
-    if let Some(user) = db_conn.get_user_by_username(auth.user_id()) {
-        if auth.password().is_some() && &user.password == auth.password().unwrap() {
-            if user.privileges.contains(&required_privilege) {
-                Ok(user.privileges.clone())
-            } else {
-                Err(format!(
-                    "Insufficient privileges for user \"{}\".",
-                    user.username
-                ))
-            }
+) -> Result<Vec<DbPrivilege>, ApiError> {
+    let user = authenticate_user(&auth, data)?;
+    if user.privileges.contains(&required_privilege) {
+        Ok(user.privileges)
+    } else {
+        Err(ApiError::Forbidden(format!(
+            "Insufficient privileges for user \"{}\".",
+            user.username
+        )))
+    }
+}
+
+fn authenticate_user(auth: &Auth, data: &web::Data<AppState>) -> Result<User, ApiError> {
+    let (auth, ip) = match auth {
+        Auth::Basic(auth, ip) => (auth, *ip),
+        Auth::Bearer(token) => {
+            return data
+                .sessions
+                .user_of(token)
+                .and_then(|username| data.db.read().unwrap().get_user_by_username(&username))
+                .ok_or_else(|| {
+                    ApiError::Unauthorized(
+                        "The session has expired, please log in again.".to_string(),
+                    )
+                })
+        }
+    };
+    let db_conn = data.db.read().unwrap();
+    let result = if let Some(user) = db_conn.get_user_by_username(auth.user_id()) {
+        if auth.password().is_some() && user.password == auth.password().unwrap() {
+            Ok(user)
         } else {
-            Err(format!("Invalid password for user \"{}\".", user.username))
+            Err(ApiError::Unauthorized(format!(
+                "Invalid password for user \"{}\".",
+                user.username
+            )))
         }
     } else {
-        Err(format!("User \"{}\" not found.", auth.user_id()))
+        Err(ApiError::Unauthorized(format!(
+            "User \"{}\" not found.",
+            auth.user_id()
+        )))
+    };
+    match result {
+        Ok(_) => data
+            .rate_limiter
+            .record_successful_login(auth.user_id(), ip),
+        Err(_) => data
+            .rate_limiter
+            .record_failed_login(auth.user_id(), ip, Instant::now()),
     }
+    result
 }
 
-#[derive(Serialize)]
-enum AuthenticationResult {
-    LoggedUser(db_access::User),
-    ErrorMessage(String),
+// Logging in with the password opens a new session,
+// while the token of an open session gives back that session.
+#[get("/authenticate")]
+async fn authenticate(auth: Auth, data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    log::debug!("authenticate()");
+    let user = authenticate_user(&auth, &data)?;
+    let token = match auth {
+        Auth::Basic(..) => data.sessions.open(&user.username),
+        Auth::Bearer(token) => token,
+    };
+    Ok(HttpResponse::Ok().json(Session { token, user }))
 }
 
-#[get("/authenticate")]
-async fn authenticate(auth: BasicAuth, data: web::Data<AppState>) -> impl Responder {
-    println!("=== authenticate() ===");
-    let db_conn = &data.db.lock().unwrap();
-    if let Some(user) = db_conn.get_user_by_username(auth.user_id()) {
-        if auth.password().is_some() && &user.password == auth.password().unwrap() {
-            HttpResponse::Ok().json(AuthenticationResult::LoggedUser(user))
-        } else {
-            HttpResponse::Forbidden().json(AuthenticationResult::ErrorMessage(
-                format!("Invalid password for user \"{}\".", user.username).to_string(),
-            ))
-        }
-    } else {
-        HttpResponse::Forbidden().json(AuthenticationResult::ErrorMessage(
-            format!("User \"{}\" not found.", auth.user_id()).to_string(),
-        ))
+#[post("/logout")]
+async fn log_out(auth: Auth, data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    log::debug!("log_out()");
+    if let Auth::Bearer(token) = auth {
+        data.sessions.close(&token);
     }
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[get("/person/{id}")]
 async fn get_person_by_id(
-    auth: BasicAuth,
+    auth: Auth,
     data: web::Data<AppState>,
     info: web::Path<(u32,)>,
-) -> impl Responder {
-    println!("=== get_person_by_id() ===");
-    match check_credentials(auth, &data, DbPrivilege::CanRead) {
-        Ok(_) => {
-            let id = info.0;
-            let db_conn = &data.db.lock().unwrap();
-            if let Some(person) = db_conn.get_person_by_id(id) {
-                HttpResponse::Ok().json(person)
-            } else {
-                HttpResponse::NotFound().finish()
-            }
-        }
-        Err(msg) => HttpResponse::Forbidden().json(&msg),
-    }
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("get_person_by_id()");
+    check_credentials(auth, &data, DbPrivilege::CanRead)?;
+    let id = info.0;
+    let db_conn = data.db.read().unwrap();
+    let person = db_conn
+        .get_person_by_id(id)
+        .ok_or_else(|| ApiError::not_found(id))?;
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(person.version))
+        .json(person))
 }
 
 #[derive(Deserialize)]
@@ -89,21 +143,111 @@ pub struct Filter {
 
 #[get("/persons")]
 async fn get_persons(
-    auth: BasicAuth,
+    auth: Auth,
     data: web::Data<AppState>,
     query: web::Query<Filter>,
-) -> impl Responder {
-    println!("=== get_persons() ===");
-    match check_credentials(auth, &data, DbPrivilege::CanRead) {
-        Ok(_) => {
-            let db_conn = &data.db.lock().unwrap();
-            let partial_name = &query.partial_name.clone().unwrap_or("".to_string());
-            let persons = db_conn
-                .get_persons_by_partial_name(partial_name)
-                .collect::<Vec<_>>();
-            HttpResponse::Ok().json(persons)
-        }
-        Err(msg) => HttpResponse::Forbidden().json(&msg),
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("get_persons()");
+    check_credentials(auth, &data, DbPrivilege::CanRead)?;
+    let db_conn = data.db.read().unwrap();
+    let partial_name = &query.partial_name.clone().unwrap_or("".to_string());
+    let persons = db_conn
+        .get_persons_by_partial_name(partial_name)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(persons))
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    since: Option<u64>,
+}
+
+#[get("/persons/events")]
+async fn person_events(
+    req: HttpRequest,
+    auth: Option<Auth>,
+    data: web::Data<AppState>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("person_events()");
+    // Browsers cannot send headers with an `EventSource`, so they send the token in the query.
+    let auth = auth
+        .or_else(|| Auth::from_query(&req))
+        .ok_or_else(|| ApiError::Unauthorized("Missing credentials.".to_string()))?;
+    // The stream of a session ends when the user logs out.
+    let closed = match &auth {
+        Auth::Bearer(token) => data.sessions.closed(token).left_future(),
+        Auth::Basic(..) => future::pending().right_future(),
+    };
+    check_credentials(auth, &data, DbPrivilege::CanRead)?;
+    // Browsers resend the id of the last received event when reconnecting.
+    let since = query.since.or_else(|| {
+        req.headers()
+            .get("Last-Event-ID")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    });
+    let subscription = data.db.write().unwrap().subscribe(since);
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(events::event_stream(subscription, closed)))
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: Option<Format>,
+}
+
+#[get("/persons/export")]
+async fn export_persons(
+    auth: Auth,
+    data: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("export_persons()");
+    check_credentials(auth, &data, DbPrivilege::CanRead)?;
+    let format = query.format.unwrap_or(Format::Json);
+    let persons = data.db.read().unwrap().get_all_persons();
+    Ok(HttpResponse::Ok()
+        .content_type(match format {
+            Format::Csv => "text/csv",
+            Format::Json => "application/json",
+        })
+        .streaming(import_export::export_stream(persons, format)))
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    format: Option<Format>,
+    atomic: Option<bool>,
+}
+
+#[post("/persons/import")]
+async fn import_persons(
+    req: HttpRequest,
+    auth: Auth,
+    data: web::Data<AppState>,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("import_persons()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let format = query
+        .format
+        .unwrap_or(if req.content_type().eq_ignore_ascii_case("text/csv") {
+            Format::Csv
+        } else {
+            Format::Json
+        });
+    let atomic = query.atomic.unwrap_or(false);
+    let mut db_conn = data.db.write().unwrap();
+    let report =
+        import_export::import(&mut db_conn, &body, format, atomic).map_err(ApiError::BadRequest)?;
+    if atomic && report.imported < report.rows.len() {
+        Ok(HttpResponse::UnprocessableEntity().json(report))
+    } else {
+        Ok(HttpResponse::Ok().json(report))
     }
 }
 
@@ -112,84 +256,685 @@ pub struct ToDelete {
     id_list: Option<String>,
 }
 
+// Deletes the person of an item of the form "id:version" of a list to delete,
+// so that, like the deletion of a single person, it must be based on its current version.
+fn delete_listed(db_conn: &mut db_access::DbConnection, item: &str) -> DeleteResult {
+    let (id, version) = match item.split_once(':') {
+        Some((id, version)) => (id, Some(version)),
+        None => (item, None),
+    };
+    let result = |outcome, current_version| DeleteResult {
+        id: id.to_string(),
+        outcome,
+        current_version,
+    };
+    let Ok(id) = id.trim().parse::<u32>() else {
+        return result(DeleteOutcome::InvalidId, None);
+    };
+    let Some(current) = db_conn.get_person_by_id(id) else {
+        return result(DeleteOutcome::NotFound, None);
+    };
+    match version.map(|v| v.trim().parse::<u32>()) {
+        None => result(DeleteOutcome::PreconditionRequired, None),
+        Some(Ok(version)) if version == current.version => {
+            db_conn.delete_by_id(id);
+            result(DeleteOutcome::Deleted, None)
+        }
+        Some(_) => result(DeleteOutcome::PreconditionFailed, Some(current.version)),
+    }
+}
+
 #[delete("/persons")]
 async fn delete_persons(
-    auth: BasicAuth,
+    auth: Auth,
     data: web::Data<AppState>,
     query: web::Query<ToDelete>,
-) -> impl Responder {
-    println!("=== delete_persons() ===");
-    match check_credentials(auth, &data, DbPrivilege::CanWrite) {
-        Ok(_) => {
-            let db_conn = &mut data.db.lock().unwrap();
-            let mut deleted_count = 0;
-            query
-                .id_list
-                .clone()
-                .unwrap_or("".to_string())
-                .split_terminator(',')
-                .for_each(|id| {
-                    deleted_count += if db_conn.delete_by_id(id.parse::<u32>().unwrap()) {
-                        1
-                    } else {
-                        0
-                    };
-                });
-            HttpResponse::Ok().json(deleted_count)
-        }
-        Err(msg) => HttpResponse::Forbidden().json(&msg),
-    }
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("delete_persons()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let id_list = query
+        .id_list
+        .clone()
+        .ok_or_else(|| ApiError::BadRequest("Missing id_list parameter.".to_string()))?;
+    let mut db_conn = data.db.write().unwrap();
+    let results = id_list
+        .split_terminator(',')
+        .map(|item| delete_listed(&mut db_conn, item))
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(DeleteReport {
+        deleted: results
+            .iter()
+            .filter(|r| matches!(r.outcome, DeleteOutcome::Deleted))
+            .count(),
+        results,
+    }))
+}
+
+#[delete("/person/{id}")]
+async fn delete_person(
+    req: HttpRequest,
+    auth: Auth,
+    data: web::Data<AppState>,
+    info: web::Path<(u32,)>,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("delete_person()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let id = info.0;
+    let mut db_conn = data.db.write().unwrap();
+    let current = db_conn
+        .get_person_by_id(id)
+        .ok_or_else(|| ApiError::not_found(id))?;
+    check_if_match(&req, current.version)?;
+    db_conn.delete_by_id(id);
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[post("/one_person")]
 async fn insert_person(
-    auth: BasicAuth,
+    auth: Auth,
     data: web::Data<AppState>,
     person: web::Json<InsertingPerson>,
-) -> impl Responder {
-    println!("=== insert_person() ===");
-    match check_credentials(auth, &data, DbPrivilege::CanWrite) {
-        Ok(_) => {
-            let db_conn = &mut data.db.lock().unwrap();
-            let new_id = db_conn.insert_person(person.into_inner());
-            HttpResponse::Ok().json(new_id)
-        }
-        Err(msg) => HttpResponse::Forbidden().json(&msg),
-    }
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("insert_person()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let mut db_conn = data.db.write().unwrap();
+    let new_id = db_conn.insert_person(person.into_inner())?;
+    Ok(HttpResponse::Ok().insert_header(etag(1)).json(new_id))
 }
 
 #[put("/one_person")]
 async fn update_person(
-    auth: BasicAuth,
+    req: HttpRequest,
+    auth: Auth,
     data: web::Data<AppState>,
-    person: web::Json<Person>,
-) -> impl Responder {
-    println!("=== update_person() ===");
-    match check_credentials(auth, &data, DbPrivilege::CanWrite) {
-        Ok(_) => {
-            let db_conn = &mut data.db.lock().unwrap();
-            let person = person.into_inner();
-            println!("updating person: {:?}", person);
-            let updated = db_conn.update_person(person);
-            HttpResponse::Ok().json(updated)
-        }
-        Err(msg) => HttpResponse::Forbidden().json(&msg),
-    }
+    person: web::Json<UpdatingPerson>,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("update_person()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let mut db_conn = data.db.write().unwrap();
+    let person = person.into_inner();
+    log::debug!("updating person: {:?}", person);
+    let id = person.id;
+    let current = db_conn
+        .get_person_by_id(id)
+        .ok_or_else(|| ApiError::not_found(id))?;
+    check_if_match(&req, current.version)?;
+    let version = db_conn
+        .update_person(person)?
+        .ok_or_else(|| ApiError::not_found(id))?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).json(true))
+}
+
+#[patch("/one_person")]
+async fn patch_person(
+    req: HttpRequest,
+    auth: Auth,
+    data: web::Data<AppState>,
+    patch: web::Json<PersonPatch>,
+) -> Result<HttpResponse, ApiError> {
+    log::debug!("patch_person()");
+    check_credentials(auth, &data, DbPrivilege::CanWrite)?;
+    let mut db_conn = data.db.write().unwrap();
+    let patch = patch.into_inner();
+    log::debug!("patching person: {:?}", patch);
+    let id = patch.id;
+    let current = db_conn
+        .get_person_by_id(id)
+        .ok_or_else(|| ApiError::not_found(id))?;
+    check_if_match(&req, current.version)?;
+    let version = db_conn
+        .patch_person(patch)?
+        .ok_or_else(|| ApiError::not_found(id))?;
+    Ok(HttpResponse::Ok().insert_header(etag(version)).json(true))
+}
+
+// Registers the resources of the API, with their configuration.
+fn configure_api(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+        .app_data(
+            web::JsonConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            web::QueryConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .app_data(
+            web::PathConfig::default()
+                .error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()),
+        )
+        .service(get_person_by_id)
+        .service(get_persons)
+        .service(person_events)
+        .service(export_persons)
+        .service(import_persons)
+        .service(delete_persons)
+        .service(delete_person)
+        .service(insert_person)
+        .service(update_person)
+        .service(patch_person)
+        .service(authenticate)
+        .service(log_out);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    let config = ServerConfig::load("PERSONS_DB", 3000).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    server_telemetry::init_logging(&config.log.level);
+
+    let rate_limit_settings = config
+        .section::<RateLimitSettings>("rate_limit")
+        .unwrap_or_else(|e| {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        });
+    let rate_limiter = Arc::new(RateLimiter::new(rate_limit_settings));
+    let session_settings = config
+        .section::<SessionSettings>("sessions")
+        .unwrap_or_else(|e| {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        });
+    let sessions = Arc::new(Sessions::new(session_settings));
+    let app_state = web::Data::new(AppState {
+        db: RwLock::new(db_access::DbConnection::new()),
+        rate_limiter: rate_limiter.clone(),
+        sessions: sessions.clone(),
+    });
+    let telemetry = Telemetry::new({
+        let app_state = app_state.clone();
+        move || {
+            if app_state.db.is_poisoned() {
+                Err("the database is poisoned by a panic".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    });
+    let allowed_origins = config.server.allowed_origins.clone();
+    log::info!(
+        "Listening at address {}:{} ...",
+        config.server.bind_address,
+        config.server.port
+    );
+    HttpServer::new(move || {
+        // Browser clients send the token of their session with every request and If-Match on changes,
+        // and read back the ETag to base the next change on.
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .allowed_headers(vec![
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+            ])
+            .expose_headers(vec![header::ETAG]);
         App::new()
-            .wrap(Cors::default().allowed_origin("http://127.0.0.1:8080"))
-            .app_data(Config::default().realm("PersonsApp"))
-            .app_data(web::Data::new(AppState {
-                db: Mutex::new(db_access::DbConnection::new()),
-            }))
-            .service(get_person_by_id)
-            .service(authenticate)
+            .wrap(rate_limiter.middleware(sessions.clone()))
+            .wrap(cors)
+            .wrap(telemetry.middleware())
+            .configure(|cfg| telemetry.configure(cfg))
+            .app_data(app_state.clone())
+            .configure(configure_api)
     })
-    .bind(("127.0.0.1", 3000))?
+    .bind((config.server.bind_address, config.server.port))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use persons_api::models::{ImportReport, Person};
+
+    use super::*;
+
+    // Basic credentials of a user who can write.
+    const SUSAN: &str = "Basic c3VzYW46eHN1c2Fu";
+
+    fn app_state() -> web::Data<AppState> {
+        web::Data::new(AppState {
+            db: RwLock::new(db_access::DbConnection::new()),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimitSettings::default())),
+            sessions: Arc::new(Sessions::new(SessionSettings::default())),
+        })
+    }
+
+    fn etag_of<B>(response: &actix_web::dev::ServiceResponse<B>) -> Option<&str> {
+        response.headers().get(header::ETAG)?.to_str().ok()
+    }
+
+    fn insert(name: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/one_person")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .set_json(InsertingPerson {
+                name: name.to_string(),
+                ..InsertingPerson::default()
+            })
+    }
+
+    fn put(id: u32, name: &str) -> test::TestRequest {
+        test::TestRequest::put()
+            .uri("/one_person")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .set_json(UpdatingPerson {
+                id,
+                person: InsertingPerson {
+                    name: name.to_string(),
+                    ..InsertingPerson::default()
+                },
+            })
+    }
+
+    fn delete(id: u32) -> test::TestRequest {
+        test::TestRequest::delete()
+            .uri(&format!("/person/{}", id))
+            .insert_header((header::AUTHORIZATION, SUSAN))
+    }
+
+    #[actix_web::test]
+    async fn second_update_from_the_same_version_fails() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let id: u32 = test::call_and_read_body_json(&app, insert("Ann").to_request()).await;
+
+        let first = test::call_service(
+            &app,
+            put(id, "Ann Smith")
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(etag_of(&first), Some("\"2\""));
+
+        let second = test::call_service(
+            &app,
+            put(id, "Ann Jones")
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(second.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(etag_of(&second), Some("\"2\""));
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/person/{}", id))
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .to_request();
+        let person: Person = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(person.name, "Ann Smith");
+        assert_eq!(person.version, 2);
+    }
+
+    #[actix_web::test]
+    async fn changes_without_if_match_are_refused() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let id: u32 = test::call_and_read_body_json(&app, insert("Ann").to_request()).await;
+
+        let response = test::call_service(&app, put(id, "Ann Smith").to_request()).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+        let response = test::call_service(&app, delete(id).to_request()).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+    }
+
+    #[actix_web::test]
+    async fn delete_with_stale_if_match_fails() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let id: u32 = test::call_and_read_body_json(&app, insert("Ann").to_request()).await;
+        let response = test::call_service(
+            &app,
+            put(id, "Ann Smith")
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = test::call_service(
+            &app,
+            delete(id)
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(etag_of(&response), Some("\"2\""));
+
+        let response = test::call_service(
+            &app,
+            delete(id)
+                .insert_header((header::IF_MATCH, "\"2\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn bulk_delete_requires_current_versions() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let ann: u32 = test::call_and_read_body_json(&app, insert("Ann").to_request()).await;
+        let bob: u32 = test::call_and_read_body_json(&app, insert("Bob").to_request()).await;
+        let cid: u32 = test::call_and_read_body_json(&app, insert("Cid").to_request()).await;
+        let response = test::call_service(
+            &app,
+            put(bob, "Bob Smith")
+                .insert_header((header::IF_MATCH, "\"1\""))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/persons?id_list={}:1,{}:1,{}", ann, bob, cid))
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .to_request();
+        let report: DeleteReport = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(report.deleted, 1);
+        let outcomes = report
+            .results
+            .iter()
+            .map(|r| (r.outcome, r.current_version))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                (DeleteOutcome::Deleted, None),
+                (DeleteOutcome::PreconditionFailed, Some(2)),
+                (DeleteOutcome::PreconditionRequired, None),
+            ]
+        );
+    }
+
+    fn list_with_token(token: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/persons")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn tags_with_semicolons_are_refused() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let request = test::TestRequest::post()
+            .uri("/one_person")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .set_json(InsertingPerson {
+                name: "Ann".to_string(),
+                tags: vec!["friends;family".to_string()],
+                ..InsertingPerson::default()
+            })
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    fn export_csv() -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/persons/export?format=csv")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+    }
+
+    #[actix_web::test]
+    async fn csv_export_has_a_header_row_even_when_empty() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let body = test::call_and_read_body(&app, export_csv().to_request()).await;
+        assert_eq!(
+            body,
+            "id,name,email,phone,birth_date,tags,created_at,updated_at,version\n"
+        );
+
+        // The header matches the columns of the rows, as they can be imported back.
+        test::call_service(&app, insert("Ann").to_request()).await;
+        let body = test::call_and_read_body(&app, export_csv().to_request()).await;
+        let request = test::TestRequest::post()
+            .uri("/persons/import?format=csv")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .set_payload(body)
+            .to_request();
+        let report: ImportReport = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(report.imported, 1);
+    }
+
+    fn import(query: &str, body: &'static str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/persons/import?{}", query))
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .set_payload(body)
+    }
+
+    fn get_all() -> test::TestRequest {
+        test::TestRequest::get()
+            .uri("/persons")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+    }
+
+    #[actix_web::test]
+    async fn csv_import_reports_the_errors_of_each_row() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let request = import(
+            "format=csv",
+            "name,email,birth_date,tags\n\
+             Ann,ann@example.com,1990-02-03,friends;work\n\
+             Bob,not an email,,\n\
+             Carl,,not a date,\n",
+        );
+        let report: ImportReport = test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.rows.len(), 3);
+        assert!(report.rows[0].id.is_some() && report.rows[0].errors.is_empty());
+        assert_eq!(report.rows[1].id, None);
+        assert_eq!(report.rows[1].errors[0].field, "email");
+        assert_eq!(report.rows[2].id, None);
+        assert_eq!(report.rows[2].errors[0].field, "row");
+
+        let persons: Vec<Person> =
+            test::call_and_read_body_json(&app, get_all().to_request()).await;
+        assert_eq!(persons.len(), 1);
+        assert_eq!(persons[0].tags, ["friends", "work"]);
+    }
+
+    #[actix_web::test]
+    async fn json_import_requires_an_array_of_persons() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let response = test::call_service(
+            &app,
+            import("format=json", r#"{"name": "Ann"}"#).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = import("format=json", r#"[{"name": "Ann"}, {"nom": "Bob"}]"#);
+        let report: ImportReport = test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.rows[1].errors[0].field, "row");
+    }
+
+    #[actix_web::test]
+    async fn atomic_import_is_rolled_back_on_any_error() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        // The second row is only invalid once the first one has been inserted.
+        let request = import(
+            "format=json&atomic=true",
+            r#"[
+                {"name": "Ann", "email": "ann@example.com"},
+                {"name": "Annie", "email": "ANN@example.com"}
+            ]"#,
+        );
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let report: ImportReport = test::read_body_json(response).await;
+        assert_eq!(report.imported, 0);
+        assert!(report.rows.iter().all(|row| row.id.is_none()));
+        assert_eq!(report.rows[1].errors[0].field, "email");
+        let persons: Vec<Person> =
+            test::call_and_read_body_json(&app, get_all().to_request()).await;
+        assert!(persons.is_empty());
+
+        // Without atomicity, the valid rows are kept.
+        let request = import(
+            "format=json",
+            r#"[
+                {"name": "Ann", "email": "ann@example.com"},
+                {"name": "Annie", "email": "ANN@example.com"}
+            ]"#,
+        );
+        let report: ImportReport = test::call_and_read_body_json(&app, request.to_request()).await;
+        assert_eq!(report.imported, 1);
+        let persons: Vec<Person> =
+            test::call_and_read_body_json(&app, get_all().to_request()).await;
+        assert_eq!(persons.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn session_token_authenticates_until_logout() {
+        let app =
+            test::init_service(App::new().app_data(app_state()).configure(configure_api)).await;
+        let request = test::TestRequest::get()
+            .uri("/authenticate")
+            .insert_header((header::AUTHORIZATION, SUSAN))
+            .to_request();
+        let session: Session = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(session.user.username, "susan");
+        assert_eq!(session.user.password, "");
+
+        let response = test::call_service(&app, list_with_token(&session.token).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/logout")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", session.token)))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = test::call_service(&app, list_with_token(&session.token).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn idle_session_expires() {
+        let app_state = web::Data::new(AppState {
+            db: RwLock::new(db_access::DbConnection::new()),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimitSettings::default())),
+            sessions: Arc::new(Sessions::new(SessionSettings {
+                idle_timeout_minutes: 0,
+            })),
+        });
+        let token = app_state.sessions.open("susan");
+        let app = test::init_service(App::new().app_data(app_state).configure(configure_api)).await;
+
+        let response = test::call_service(&app, list_with_token(&token).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, list_with_token("unknown").to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn event_stream_accepts_the_token_in_the_query() {
+        let app_state = app_state();
+        let token = app_state.sessions.open("joe");
+        let app = test::init_service(App::new().app_data(app_state).configure(configure_api)).await;
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/persons/events?access_token={}", token))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        let request = test::TestRequest::get().uri("/persons/events").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let request = test::TestRequest::get()
+            .uri("/persons/events?access_token=unknown")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn event_stream_ends_when_logging_out() {
+        let app_state = app_state();
+        let token = app_state.sessions.open("joe");
+        let app = test::init_service(App::new().app_data(app_state).configure(configure_api)).await;
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/persons/events?access_token={}", token))
+            .to_request();
+        let stream = test::call_service(&app, request).await;
+        assert_eq!(stream.status(), StatusCode::OK);
+
+        let request = test::TestRequest::post()
+            .uri("/logout")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request();
+        test::call_service(&app, request).await;
+        let body = actix_web::rt::time::timeout(
+            std::time::Duration::from_secs(5),
+            test::read_body(stream),
+        )
+        .await
+        .expect("the stream should end");
+        assert!(body.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn tokens_in_the_query_are_rate_limited() {
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimitSettings {
+            ip_burst: 2.0,
+            ip_per_second: 0.001,
+            ..RateLimitSettings::default()
+        }));
+        let app_state = web::Data::new(AppState {
+            db: RwLock::new(db_access::DbConnection::new()),
+            rate_limiter: rate_limiter.clone(),
+            sessions: Arc::new(Sessions::new(SessionSettings::default())),
+        });
+        let app = test::init_service(
+            App::new()
+                .wrap(rate_limiter.middleware(app_state.sessions.clone()))
+                .app_data(app_state)
+                .configure(configure_api),
+        )
+        .await;
+
+        let mut statuses = vec![];
+        for _ in 0..3 {
+            let request = test::TestRequest::get()
+                .uri("/persons/events?access_token=guessed")
+                .peer_addr("10.0.0.1:4000".parse().unwrap())
+                .to_request();
+            match test::try_call_service(&app, request).await {
+                Ok(response) => statuses.push(response.status()),
+                Err(e) => statuses.push(e.as_response_error().status_code()),
+            }
+        }
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::UNAUTHORIZED,
+                StatusCode::UNAUTHORIZED,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use actix_web::http::header::{self, EntityTag, Header, IfMatch};
+use actix_web::HttpRequest;
+
+use crate::errors::ApiError;
+
+pub fn etag(version: u32) -> header::ETag {
+    header::ETag(EntityTag::new_strong(version.to_string()))
+}
+
+// Writes to an existing person must state which version they were based on,
+// so that a client cannot silently overwrite changes it has never seen.
+pub fn check_if_match(req: &HttpRequest, current_version: u32) -> Result<(), ApiError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Err(ApiError::PreconditionRequired);
+    }
+    let matches = match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => true,
+        Ok(IfMatch::Items(tags)) => tags
+            .iter()
+            .any(|tag| tag.strong_eq(&EntityTag::new_strong(current_version.to_string()))),
+        Err(_) => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(ApiError::PreconditionFailed { current_version })
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+use serde_derive::Deserialize;
+
+use crate::errors::ApiError;
+use crate::sessions::{Auth, Sessions};
+
+// Beyond this number of tracked clients, the ones that are idle are forgotten.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub ip_burst: f64,
+    pub ip_per_second: f64,
+    pub user_burst: f64,
+    pub user_per_second: f64,
+    pub max_failed_logins: u32,
+    pub lockout_seconds: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> RateLimitSettings {
+        RateLimitSettings {
+            ip_burst: 30.0,
+            ip_per_second: 10.0,
+            user_burst: 10.0,
+            user_per_second: 2.0,
+            max_failed_logins: 5,
+            lockout_seconds: 300,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    // Takes a token if available, otherwise returns how long to wait for the next one.
+    fn take(&mut self, burst: f64, per_second: f64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+
+    fn is_full(&self, burst: f64, per_second: f64, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * per_second >= burst
+    }
+}
+
+#[derive(Default)]
+struct FailedLogins {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+// The failed logins are counted per user and client address,
+// so that others cannot lock a user out.
+type LoginKey = (String, Option<IpAddr>);
+
+#[derive(Default)]
+struct State {
+    ip_buckets: HashMap<IpAddr, TokenBucket>,
+    user_buckets: HashMap<String, TokenBucket>,
+    failed_logins: HashMap<LoginKey, FailedLogins>,
+}
+
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    state: Mutex<State>,
+}
+
+fn take_token<K: Hash + Eq>(
+    buckets: &mut HashMap<K, TokenBucket>,
+    key: K,
+    burst: f64,
+    per_second: f64,
+    now: Instant,
+) -> Result<(), Duration> {
+    if buckets.len() >= MAX_TRACKED_KEYS {
+        buckets.retain(|_, b| !b.is_full(burst, per_second, now));
+    }
+    buckets
+        .entry(key)
+        .or_insert(TokenBucket {
+            tokens: burst,
+            updated: now,
+        })
+        .take(burst, per_second, now)
+}
+
+fn too_many_requests(wait: Duration) -> ApiError {
+    ApiError::TooManyRequests {
+        retry_after: wait.as_secs_f64().ceil().max(1.0) as u64,
+    }
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> RateLimiter {
+        RateLimiter {
+            settings,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn check_request(
+        &self,
+        ip: Option<IpAddr>,
+        username: Option<&str>,
+        now: Instant,
+    ) -> Result<(), ApiError> {
+        let settings = &self.settings;
+        let state = &mut *self.state.lock().unwrap();
+        if let Some(ip) = ip {
+            take_token(
+                &mut state.ip_buckets,
+                ip,
+                settings.ip_burst,
+                settings.ip_per_second,
+                now,
+            )
+            .map_err(too_many_requests)?;
+        }
+        // The requests with an unknown session token are limited only per address.
+        let Some(username) = username else {
+            return Ok(());
+        };
+        take_token(
+            &mut state.user_buckets,
+            username.to_string(),
+            settings.user_burst,
+            settings.user_per_second,
+            now,
+        )
+        .map_err(too_many_requests)?;
+        Self::check_lockout(state, username, ip, now)
+    }
+
+    fn check_lockout(
+        state: &mut State,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), ApiError> {
+        match state
+            .failed_logins
+            .get(&(username.to_string(), ip))
+            .and_then(|f| f.locked_until)
+        {
+            Some(locked_until) if locked_until > now => {
+                Err(too_many_requests(locked_until.duration_since(now)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Locks out the user from the client address after too many consecutive failures.
+    pub fn record_failed_login(&self, username: &str, ip: Option<IpAddr>, now: Instant) {
+        let state = &mut *self.state.lock().unwrap();
+        if state.failed_logins.len() >= MAX_TRACKED_KEYS {
+            state.failed_logins.retain(|_, f| f.locked_until.is_some());
+        }
+        let failures = state
+            .failed_logins
+            .entry((username.to_string(), ip))
+            .or_default();
+        failures.count += 1;
+        if failures.count >= self.settings.max_failed_logins {
+            log::warn!(
+                username = username, ip:? = ip;
+                "too many failed logins, locking out the user from the address"
+            );
+            failures.count = 0;
+            failures.locked_until = Some(now + Duration::from_secs(self.settings.lockout_seconds));
+        }
+    }
+
+    pub fn record_successful_login(&self, username: &str, ip: Option<IpAddr>) {
+        self.state
+            .lock()
+            .unwrap()
+            .failed_logins
+            .remove(&(username.to_string(), ip));
+    }
+
+    // The sessions tell the users of the requests carrying a session token.
+    pub fn middleware(self: &Arc<Self>, sessions: Arc<Sessions>) -> RateLimit {
+        RateLimit {
+            limiter: self.clone(),
+            sessions,
+        }
+    }
+}
+
+// Limits the rate of the requests carrying credentials, both per client address
+// and per username, and rejects the requests of users that are locked out.
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+    sessions: Arc<Sessions>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+            sessions: self.sessions.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: Arc<RateLimiter>,
+    sessions: Arc<Sessions>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // The token in the query is checked too, not to let it be guessed without limits.
+        let auth = Auth::from_headers(req.request()).or_else(|| Auth::from_query(req.request()));
+        if let Some(auth) = auth {
+            let ip = req.peer_addr().map(|a| a.ip());
+            let username = match &auth {
+                Auth::Basic(auth, _) => Some(auth.user_id().to_string()),
+                Auth::Bearer(token) => self.sessions.user_of(token),
+            };
+            if let Err(e) = self
+                .limiter
+                .check_request(ip, username.as_deref(), Instant::now())
+            {
+                return Box::pin(async move { Err(e.into()) });
+            }
+        }
+        let service = self.service.clone();
+        Box::pin(async move { service.call(req).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: Option<&str> = Some("alice");
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([10, 0, 0, last]))
+    }
+
+    fn retry_after(result: Result<(), ApiError>) -> Option<u64> {
+        match result {
+            Err(ApiError::TooManyRequests { retry_after }) => Some(retry_after),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn take_empties_the_bucket_and_tells_the_wait() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket {
+            tokens: 2.0,
+            updated: now,
+        };
+        assert_eq!(bucket.take(2.0, 4.0, now), Ok(()));
+        assert_eq!(bucket.take(2.0, 4.0, now), Ok(()));
+        assert_eq!(bucket.take(2.0, 4.0, now), Err(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn bucket_refills_up_to_the_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated: now,
+        };
+        let later = now + Duration::from_millis(500);
+        assert_eq!(bucket.take(3.0, 2.0, later), Ok(()));
+        assert!(bucket.take(3.0, 2.0, later).is_err());
+
+        let much_later = later + Duration::from_secs(60);
+        assert!(bucket.is_full(3.0, 2.0, much_later));
+        for _ in 0..3 {
+            assert_eq!(bucket.take(3.0, 2.0, much_later), Ok(()));
+        }
+        assert!(bucket.take(3.0, 2.0, much_later).is_err());
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up_to_whole_seconds() {
+        let retry_after = |wait| retry_after(Err(too_many_requests(wait)));
+        assert_eq!(retry_after(Duration::from_millis(1500)), Some(2));
+        assert_eq!(retry_after(Duration::from_secs(3)), Some(3));
+        assert_eq!(retry_after(Duration::ZERO), Some(1));
+    }
+
+    #[test]
+    fn requests_are_limited_per_address_and_per_user() {
+        let limiter = RateLimiter::new(RateLimitSettings {
+            ip_burst: 2.0,
+            ip_per_second: 1.0,
+            user_burst: 3.0,
+            user_per_second: 0.5,
+            ..RateLimitSettings::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.check_request(ip(1), ALICE, now).is_ok());
+        assert!(limiter.check_request(ip(1), ALICE, now).is_ok());
+        assert_eq!(
+            retry_after(limiter.check_request(ip(1), ALICE, now)),
+            Some(1)
+        );
+
+        // From another address, the user still has one request left.
+        assert!(limiter.check_request(ip(2), ALICE, now).is_ok());
+        assert_eq!(
+            retry_after(limiter.check_request(ip(2), ALICE, now)),
+            Some(2)
+        );
+        assert!(limiter.check_request(ip(3), Some("bob"), now).is_ok());
+    }
+
+    #[test]
+    fn lockout_expires_and_is_per_address() {
+        let limiter = RateLimiter::new(RateLimitSettings {
+            max_failed_logins: 3,
+            lockout_seconds: 60,
+            ..RateLimitSettings::default()
+        });
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_request(ip(1), ALICE, now).is_ok());
+            limiter.record_failed_login("alice", ip(1), now);
+        }
+        assert_eq!(
+            retry_after(limiter.check_request(ip(1), ALICE, now)),
+            Some(60)
+        );
+
+        // The user can still log in from elsewhere, which does not unlock the other address.
+        assert!(limiter.check_request(ip(2), ALICE, now).is_ok());
+        limiter.record_successful_login("alice", ip(2));
+        let later = now + Duration::from_secs(45);
+        assert_eq!(
+            retry_after(limiter.check_request(ip(1), ALICE, later)),
+            Some(15)
+        );
+
+        let expired = now + Duration::from_secs(60);
+        assert!(limiter.check_request(ip(1), ALICE, expired).is_ok());
+    }
+
+    #[test]
+    fn successful_login_resets_the_failures() {
+        let limiter = RateLimiter::new(RateLimitSettings {
+            max_failed_logins: 2,
+            ..RateLimitSettings::default()
+        });
+        let now = Instant::now();
+        limiter.record_failed_login("alice", ip(1), now);
+        limiter.record_successful_login("alice", ip(1));
+        limiter.record_failed_login("alice", ip(1), now);
+        assert!(limiter.check_request(ip(1), ALICE, now).is_ok());
+        limiter.record_failed_login("alice", ip(1), now);
+        assert!(limiter.check_request(ip(1), ALICE, now).is_err());
+    }
+}
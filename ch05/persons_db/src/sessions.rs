@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::Payload;
+use actix_web::http::header::Header;
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
+use serde_derive::Deserialize;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    // Longer than the inactivity timeout of the browser clients,
+    // as the users can be active for a while without sending requests.
+    pub idle_timeout_minutes: u64,
+}
+
+impl Default for SessionSettings {
+    fn default() -> SessionSettings {
+        SessionSettings {
+            idle_timeout_minutes: 30,
+        }
+    }
+}
+
+struct Session {
+    username: String,
+    last_used: Instant,
+    // Dropped with the session, to end what has been started in it.
+    closed: watch::Sender<()>,
+}
+
+// The sessions opened by logging in, identified by random tokens,
+// so that the clients do not have to keep the passwords of the users.
+// A session is closed when it has not been used for the idle timeout.
+pub struct Sessions {
+    idle_timeout: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    pub fn new(settings: SessionSettings) -> Sessions {
+        Sessions {
+            idle_timeout: Duration::from_secs(settings.idle_timeout_minutes * 60),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns the token of the new session.
+    pub fn open(&self, username: &str) -> String {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, s| now.duration_since(s.last_used) < self.idle_timeout);
+        let token = Uuid::new_v4().simple().to_string();
+        sessions.insert(
+            token.clone(),
+            Session {
+                username: username.to_string(),
+                last_used: now,
+                closed: watch::channel(()).0,
+            },
+        );
+        token
+    }
+
+    // The user of the session, which is kept open by this use.
+    pub fn user_of(&self, token: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        match sessions.get_mut(token) {
+            Some(session) if now.duration_since(session.last_used) < self.idle_timeout => {
+                session.last_used = now;
+                Some(session.username.clone())
+            }
+            Some(_) => {
+                sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn close(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    // Completes when the session is closed or found expired,
+    // or immediately if it is not open.
+    pub fn closed(&self, token: &str) -> impl Future<Output = ()> {
+        let receiver = self
+            .sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .map(|session| session.closed.subscribe());
+        async move {
+            if let Some(mut receiver) = receiver {
+                while receiver.changed().await.is_ok() {}
+            }
+        }
+    }
+}
+
+// The token of a session given in the query, only where a header cannot be sent.
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+// The credentials of a request: the password of the user, to log in,
+// with the address of the client, to which the failed logins are counted,
+// or the token of an open session.
+pub enum Auth {
+    Basic(Basic, Option<IpAddr>),
+    Bearer(String),
+}
+
+impl Auth {
+    pub fn from_headers(req: &HttpRequest) -> Option<Auth> {
+        if let Ok(auth) = Authorization::<Bearer>::parse(req) {
+            Some(Auth::Bearer(auth.into_scheme().token().to_string()))
+        } else {
+            Authorization::<Basic>::parse(req)
+                .ok()
+                .map(|auth| Auth::Basic(auth.into_scheme(), req.peer_addr().map(|a| a.ip())))
+        }
+    }
+
+    pub fn from_query(req: &HttpRequest) -> Option<Auth> {
+        web::Query::<AccessToken>::from_query(req.query_string())
+            .ok()
+            .map(|query| Auth::Bearer(query.into_inner().access_token))
+    }
+}
+
+impl FromRequest for Auth {
+    type Error = ApiError;
+    type Future = Ready<Result<Auth, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            Auth::from_headers(req)
+                .ok_or_else(|| ApiError::Unauthorized("Missing credentials.".to_string())),
+        )
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::{NaiveDate, Utc};
+use persons_api::models;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+    // Set when the value is well-formed but clashes with another person.
+    pub conflict: bool,
+}
+
+// Clients are only told which field is wrong and why.
+impl From<FieldError> for models::FieldError {
+    fn from(error: FieldError) -> models::FieldError {
+        models::FieldError {
+            field: error.field.to_string(),
+            message: error.message,
+        }
+    }
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: &str) -> FieldError {
+        FieldError {
+            field,
+            message: message.to_string(),
+            conflict: false,
+        }
+    }
+
+    pub fn conflict(field: &'static str, message: &str) -> FieldError {
+        FieldError {
+            conflict: true,
+            ..FieldError::new(field, message)
+        }
+    }
+}
+
+pub fn validate_name(name: &str, errors: &mut Vec<FieldError>) {
+    if name.trim().is_empty() {
+        errors.push(FieldError::new("name", "must not be empty"));
+    }
+}
+
+pub fn validate_email(email: &str, errors: &mut Vec<FieldError>) {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    if !valid {
+        errors.push(FieldError::new("email", "is not a valid email address"));
+    }
+}
+
+pub fn validate_phone(phone: &str, errors: &mut Vec<FieldError>) {
+    let digits = phone.chars().filter(char::is_ascii_digit).count();
+    let allowed = phone
+        .chars()
+        .all(|c| c.is_ascii_digit() || " +-().".contains(c));
+    if !allowed || digits < 3 {
+        errors.push(FieldError::new("phone", "is not a valid phone number"));
+    }
+}
+
+pub fn validate_birth_date(birth_date: NaiveDate, errors: &mut Vec<FieldError>) {
+    if birth_date > Utc::now().date_naive() {
+        errors.push(FieldError::new("birth_date", "must not be in the future"));
+    }
+}
+
+pub fn validate_tags(tags: &[String], errors: &mut Vec<FieldError>) {
+    if tags.iter().any(|t| t.trim().is_empty()) {
+        errors.push(FieldError::new("tags", "must not contain empty tags"));
+    }
+    // The tags are joined by semicolons in the CSV exports.
+    if tags.iter().any(|t| t.contains(';')) {
+        errors.push(FieldError::new("tags", "must not contain semicolons"));
+    }
+}
@@ -0,0 +1,4 @@
+// The parts shared by the web clients of persons_db,
+// which have the same pages and sessions, whether the database is local or remote.
+pub mod routes;
+pub mod session;
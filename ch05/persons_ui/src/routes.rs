@@ -0,0 +1,92 @@
+use serde_derive::{Deserialize, Serialize};
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Routable)]
+pub enum Route {
+    #[at("/")]
+    Home,
+    #[at("/login")]
+    Login,
+    #[at("/persons")]
+    Persons,
+    #[at("/persons/new")]
+    NewPerson,
+    #[at("/persons/:id/edit")]
+    EditPerson { id: u32 },
+    #[not_found]
+    #[at("/404")]
+    NotFound,
+}
+
+// The filter of the list of persons, kept in the URL to survive reloads and to be shared.
+// It is carried along by the pages of a single person, to get back to the same list.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersonsQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+}
+
+// The page requested before being sent to log in, where to go after logging in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoginQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+}
+
+impl LoginQuery {
+    pub fn target(&self) -> (Route, PersonsQuery) {
+        let route = self
+            .redirect
+            .as_deref()
+            .and_then(Route::recognize)
+            .filter(|route| !matches!(route, Route::Login | Route::NotFound))
+            .unwrap_or(Route::Persons);
+        (
+            route,
+            PersonsQuery {
+                partial_name: self.partial_name.clone(),
+                id: self.id,
+            },
+        )
+    }
+}
+
+// A malformed query is treated as no filter at all.
+#[hook]
+pub fn use_persons_query() -> PersonsQuery {
+    use_location()
+        .and_then(|location| location.query::<PersonsQuery>().ok())
+        .unwrap_or_default()
+}
+
+#[hook]
+pub fn use_login_query() -> LoginQuery {
+    use_location()
+        .and_then(|location| location.query::<LoginQuery>().ok())
+        .unwrap_or_default()
+}
+
+// Guards the pages requiring a logged-in user,
+// sending to the login page and then back to the requested page.
+#[function_component]
+pub fn RedirectToLogin() -> Html {
+    let navigator = use_navigator().unwrap();
+    let route = use_route::<Route>();
+    let query = use_persons_query();
+    use_effect_with((), move |_| {
+        let login_query = LoginQuery {
+            redirect: route.map(|route| route.to_path()),
+            partial_name: query.partial_name,
+            id: query.id,
+        };
+        let _ = navigator.replace_with_query(&Route::Login, &login_query);
+    });
+    html! {}
+}
@@ -0,0 +1,105 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+use yew_hooks::prelude::*;
+
+// After this time without user activity, the session is closed.
+pub const INACTIVITY_TIMEOUT_MINUTES: f64 = 15.0;
+// How often the inactivity is checked.
+const INACTIVITY_CHECK_MILLIS: u32 = 30_000;
+// The activity is recorded at most this often, not to write the storage at every key stroke.
+const ACTIVITY_RECORD_MILLIS: f64 = 10_000.0;
+
+pub const EXPIRED_MESSAGE: &str =
+    "The session has expired because of inactivity, please log in again.";
+
+// `id` identifies the session: the user name when the users are in the same process,
+// otherwise the token given by the server, but never the password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredSession<T> {
+    pub id: T,
+    // Milliseconds since the Unix epoch.
+    pub last_activity: f64,
+}
+
+impl<T: Clone> StoredSession<T> {
+    pub fn new(id: T) -> StoredSession<T> {
+        StoredSession {
+            id,
+            last_activity: Date::now(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Date::now() - self.last_activity > INACTIVITY_TIMEOUT_MINUTES * 60_000.0
+    }
+
+    // Returns the session with the activity recorded, if it is worth storing.
+    pub fn touched(&self) -> Option<StoredSession<T>> {
+        let now = Date::now();
+        (now - self.last_activity > ACTIVITY_RECORD_MILLIS).then(|| StoredSession {
+            last_activity: now,
+            ..self.clone()
+        })
+    }
+}
+
+// The session is kept in the session storage, so that it survives reloads,
+// but not the closing of the tab.
+#[hook]
+pub fn use_stored_session<T>(key: &'static str) -> UseSessionStorageHandle<StoredSession<T>>
+where
+    T: Serialize + DeserializeOwned + 'static,
+{
+    use_session_storage::<StoredSession<T>>(key.to_string())
+}
+
+// While logged in, records the activity of the user in the session,
+// and calls `on_expired` when the session has been inactive for too long,
+// or `on_cleared` when the storage has been cleared meanwhile.
+#[hook]
+pub fn use_session_expiry<T>(
+    stored_session: UseSessionStorageHandle<StoredSession<T>>,
+    logged_in: bool,
+    on_expired: Callback<()>,
+    on_cleared: Callback<()>,
+) where
+    T: Clone + Serialize + DeserializeOwned + 'static,
+{
+    {
+        let stored_session = stored_session.clone();
+        let on_expired = on_expired.clone();
+        use_interval(
+            move || {
+                if logged_in
+                    && (*stored_session)
+                        .as_ref()
+                        .is_none_or(StoredSession::is_expired)
+                {
+                    on_expired.emit(());
+                }
+            },
+            INACTIVITY_CHECK_MILLIS,
+        );
+    }
+
+    let on_activity = move |_: Event| {
+        if !logged_in {
+            return;
+        }
+        match (*stored_session).clone() {
+            // The timer may have been suspended, together with the computer.
+            Some(session) if session.is_expired() => on_expired.emit(()),
+            Some(session) => {
+                if let Some(touched) = session.touched() {
+                    stored_session.set(touched);
+                }
+            }
+            None => on_cleared.emit(()),
+        }
+    };
+    use_event_with_window("click", on_activity.clone());
+    use_event_with_window("keydown", on_activity);
+}
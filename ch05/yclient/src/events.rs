@@ -0,0 +1,55 @@
+use persons_api::models::ChangeEvent;
+use web_sys::wasm_bindgen::closure::Closure;
+use web_sys::wasm_bindgen::JsCast;
+use web_sys::{EventSource, MessageEvent};
+use yew::prelude::*;
+use yew_hooks::prelude::*;
+
+// The names of the events of the stream carrying a change.
+const CHANGE_EVENTS: [&str; 3] = ["insert", "update", "delete"];
+
+// While the component is shown, listens to the stream of changes at `url`,
+// calling `on_change` for each change, or `on_reset` when some changes have been missed,
+// so that everything has to be reloaded.
+// The browser reconnects by itself, asking for the changes made meanwhile.
+#[hook]
+pub fn use_person_events(
+    url: Option<String>,
+    on_change: Callback<ChangeEvent>,
+    on_reset: Callback<()>,
+) {
+    // The callbacks change at every rendering, while the stream is kept open.
+    let on_change = use_latest(on_change);
+    let on_reset = use_latest(on_reset);
+    use_effect_with(url, move |url| {
+        let source = url.as_deref().and_then(|url| EventSource::new(url).ok());
+        let change_listener = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+            if let Some(change) = event
+                .data()
+                .as_string()
+                .and_then(|data| serde_json::from_str(&data).ok())
+            {
+                on_change.current().emit(change);
+            }
+        });
+        let reset_listener =
+            Closure::<dyn Fn(MessageEvent)>::new(move |_| on_reset.current().emit(()));
+        if let Some(source) = &source {
+            for name in CHANGE_EVENTS {
+                let _ = source.add_event_listener_with_callback(
+                    name,
+                    change_listener.as_ref().unchecked_ref(),
+                );
+            }
+            let _ = source
+                .add_event_listener_with_callback("reset", reset_listener.as_ref().unchecked_ref());
+        }
+        move || {
+            if let Some(source) = source {
+                source.close();
+            }
+            drop(change_listener);
+            drop(reset_listener);
+        }
+    });
+}
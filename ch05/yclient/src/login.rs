@@ -41,7 +41,9 @@ pub fn LoginForm(
         let username = username.clone();
         let password = password.clone();
         let props_on_submit = props_on_submit.clone();
-        move |_| {
+        move |e: SubmitEvent| {
+            // Otherwise the browser would reload the page.
+            e.prevent_default();
             props_on_submit.emit(LoginFormValues {
                 username: (*username).to_string(),
                 password: (*password).to_string(),
@@ -57,7 +59,7 @@ pub fn LoginForm(
             </div>
             <div>
                 <label>{ "Password: " }</label>
-                <input value={(*password).to_string()} onchange={on_change_password} />
+                <input type="password" value={(*password).to_string()} onchange={on_change_password} />
             </div>
             <button type="submit">{ "Log in" }</button>
         </form>
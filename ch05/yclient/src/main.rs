@@ -1,58 +1,203 @@
+use persons_api::client::{Client, ClientError, Credentials};
+use persons_api::models::{DbPrivilege, User};
+use persons_ui::routes::{use_login_query, RedirectToLogin, Route};
+use persons_ui::session::{use_session_expiry, use_stored_session, StoredSession, EXPIRED_MESSAGE};
 use web_sys::wasm_bindgen::JsValue;
 use yew::prelude::*;
+use yew_router::prelude::*;
 
-use crate::common::{request, LoggingUser, User};
+use crate::common::BACKEND_SITE;
 use crate::login::{LoginForm, LoginFormValues};
+use crate::one_person::OnePerson;
 use crate::persons_list::PersonsList;
 
 mod common;
+mod events;
 mod login;
+mod one_person;
 mod persons_list;
 
-enum Page {
-    Login,
-    PersonsList,
-    OnePerson,
-}
+// Distinct for each app, as they can be served by the same site.
+const SESSION_STORAGE_KEY: &str = "yclient.session";
 
 #[function_component]
 fn App() -> Html {
-    let username: UseStateHandle<Option<String>> = use_state(|| None);
-    let page = use_state(|| Page::Login);
+    html! {
+        <BrowserRouter>
+            <Content />
+        </BrowserRouter>
+    }
+}
+
+#[function_component]
+fn Content() -> Html {
+    let navigator = use_navigator().unwrap();
+    let login_query = use_login_query();
+    let user: UseStateHandle<Option<User>> = use_state(|| None);
+    // Sends the token of the session with every request.
+    let client: UseStateHandle<Option<Client>> = use_state(|| None);
+    // Identified by the token given by the server.
+    let stored_session = use_stored_session::<String>(SESSION_STORAGE_KEY);
+    // The pages are not shown until the stored session has been checked,
+    // not to be sent to the login page meanwhile.
+    let restoring = use_state(|| stored_session.is_some());
+    let loading = use_state(|| false);
+    let error: UseStateHandle<Option<String>> = use_state(|| None);
+
+    {
+        let stored_session = stored_session.clone();
+        let user = user.clone();
+        let client = client.clone();
+        let restoring = restoring.clone();
+        let error = error.clone();
+        use_effect_with((), move |_| match (*stored_session).clone() {
+            Some(session) if session.is_expired() => {
+                stored_session.delete();
+                error.set(Some(EXPIRED_MESSAGE.to_string()));
+                restoring.set(false);
+            }
+            Some(session) => {
+                let new_client = Client::new(BACKEND_SITE).with_token(session.id);
+                yew::platform::spawn_local(async move {
+                    match new_client.authenticate().await {
+                        Ok(session) => {
+                            user.set(Some(session.user));
+                            client.set(Some(new_client));
+                        }
+                        // Kept if the server could not be reached, to retry at the next reload.
+                        Err(e) => {
+                            if matches!(e, ClientError::Unauthorized(_)) {
+                                stored_session.delete();
+                            }
+                            error.set(Some(e.to_string()));
+                        }
+                    }
+                    restoring.set(false);
+                });
+            }
+            None => {}
+        });
+    }
+
+    let log_out = {
+        let user = user.clone();
+        let client = client.clone();
+        let stored_session = stored_session.clone();
+        let error = error.clone();
+        move |message: Option<String>| {
+            // The session is closed also on the server, not to leave its token usable.
+            if let Some(old_client) = (*client).clone() {
+                yew::platform::spawn_local(async move {
+                    let _ = old_client.log_out().await;
+                });
+            }
+            user.set(None);
+            client.set(None);
+            stored_session.delete();
+            error.set(message);
+        }
+    };
+
+    // When the session expires, the guards of the pages send to the login page.
+    use_session_expiry(
+        stored_session.clone(),
+        client.is_some(),
+        Callback::from({
+            let log_out = log_out.clone();
+            move |_| log_out(Some(EXPIRED_MESSAGE.to_string()))
+        }),
+        Callback::from({
+            let log_out = log_out.clone();
+            move |_| log_out(None)
+        }),
+    );
 
     let on_submit_login_form = {
-        let username = username.clone();
-        // let page = page.clone();
+        let navigator = navigator.clone();
+        let user = user.clone();
+        let client = client.clone();
+        let stored_session = stored_session.clone();
+        let loading = loading.clone();
+        let error = error.clone();
         Callback::from(move |data: LoginFormValues| {
-            // username.set(Some(data.username));
-            // let username = username.clone();
-            // let page = page.clone();
-            // let data = data.clone();
-            // web_sys::console::log_1(&JsValue::from(format!(
-            //     "username={}, password={}",
-            //     data.username, data.password
-            // )));
-            // yew::platform::spawn_local(async move {
-            //     match request::<(), User>(
-            //         reqwest::Method::GET,
-            //         "/authenticate".to_string(),
-            //         Some(LoggingUser {
-            //             username: data.username.clone(),
-            //             password: data.password.clone(),
-            //         }),
-            //         (),
-            //     )
-            //     .await
-            //     {
-            //         Ok(u) => {
-            //             username.set(Some(u.username.clone()));
-            //             page.set(Page::PersonsList);
-            //         }
-            //         Err(msg) => {
-            //             web_sys::console::log_1(&JsValue::from(msg));
-            //         }
-            //     }
-            // });
+            let navigator = navigator.clone();
+            let (route, query) = login_query.target();
+            let user = user.clone();
+            let client = client.clone();
+            let stored_session = stored_session.clone();
+            let loading = loading.clone();
+            let error = error.clone();
+            let login_client = Client::new(BACKEND_SITE).with_credentials(Credentials {
+                username: data.username,
+                password: data.password,
+            });
+            loading.set(true);
+            error.set(None);
+            yew::platform::spawn_local(async move {
+                match login_client.authenticate().await {
+                    Ok(session) => {
+                        stored_session.set(StoredSession::new(session.token.clone()));
+                        user.set(Some(session.user));
+                        client.set(Some(Client::new(BACKEND_SITE).with_token(session.token)));
+                        let _ = navigator.push_with_query(&route, &query);
+                    }
+                    Err(e) => {
+                        web_sys::console::log_1(&JsValue::from(e.to_string()));
+                        error.set(Some(e.to_string()));
+                    }
+                }
+                loading.set(false);
+            });
+        })
+    };
+
+    let on_log_out = move |_| {
+        log_out(None);
+        navigator.push(&Route::Login);
+    };
+
+    let can_write = (*user)
+        .as_ref()
+        .is_some_and(|u| u.can(DbPrivilege::CanWrite));
+
+    let render = {
+        let client = (*client).clone();
+        let loading = *loading;
+        let error = (*error).clone();
+        Callback::from(move |route: Route| match (route, client.clone()) {
+            (Route::Login, _) => html! {
+                <div>
+                    <LoginForm on_submit={on_submit_login_form.clone()} />
+                    if loading {
+                        <p>{ "Logging in..." }</p>
+                    }
+                    if let Some(error) = error.clone() {
+                        <p class="error">{ error }</p>
+                    }
+                </div>
+            },
+            (Route::NotFound, _) => html! {
+                <p>{ "Page not found." }</p>
+            },
+            (_, None) => html! {
+                <RedirectToLogin />
+            },
+            (Route::Home, Some(_)) => html! {
+                <Redirect<Route> to={Route::Persons} />
+            },
+            (Route::Persons, Some(client)) => html! {
+                <PersonsList {client} {can_write} />
+            },
+            (Route::NewPerson, Some(_)) if !can_write => html! {
+                <Redirect<Route> to={Route::Persons} />
+            },
+            // Keyed, so that the form is not reused for another person.
+            (Route::NewPerson, Some(client)) => html! {
+                <OnePerson key="new" id={None} {client} {can_write} />
+            },
+            (Route::EditPerson { id }, Some(client)) => html! {
+                <OnePerson key={id.to_string()} id={Some(id)} {client} {can_write} />
+            },
         })
     };
 
@@ -60,7 +205,7 @@ fn App() -> Html {
         <div>
             <style>
             {
-                ".current-user { color: #0000C0 }"
+                ".current-user { color: #0000C0 } .error { color: #C00000 }"
             }
             </style>
             <header>
@@ -69,37 +214,26 @@ fn App() -> Html {
                     { "Current User: " }
                     <span class="current-user">
                     {
-                        if let Some(username) = (*username).clone() {
-                            username.clone()
+                        if let Some(user) = (*user).clone() {
+                            user.username
                         } else {
                             "---".to_string()
                         }
                     }
                     </span>
-                    {
-                        match *page {
-                            Page::Login => html! { <div/> },
-                            _ => html! {
-                                <span>
-                                    {" "}
-                                    <button>{ "Change User" }</button>
-                                </span>
-                            }
-                        }
+                    if user.is_some() {
+                        <span>
+                            {" "}
+                            <button onclick={on_log_out}>{ "Log Out" }</button>
+                        </span>
                     }
                 </p>
                 <hr/>
             </header>
-            {
-                match *page {
-                    Page::Login => html! {
-                        <LoginForm on_submit={on_submit_login_form} />
-                    },
-                    Page::PersonsList => html! {
-                        <PersonsList />
-                    },
-                    Page::OnePerson => html! {},
-                }
+            if *restoring {
+                <p>{ "Restoring the session..." }</p>
+            } else {
+                <Switch<Route> {render} />
             }
             <footer>
                 <hr/>
@@ -0,0 +1,196 @@
+use persons_api::client::{Client, ClientError};
+use persons_api::models::{InsertingPerson, PersonPatch};
+use persons_ui::routes::{use_persons_query, Route};
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+#[derive(PartialEq, Clone, Properties)]
+pub struct OnePersonProps {
+    // `None` to insert a new person.
+    pub id: Option<u32>,
+    pub client: Client,
+    pub can_write: bool,
+}
+
+// Empty fields are sent as missing values.
+fn optional(value: &AttrValue) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[function_component]
+pub fn OnePerson(props: &OnePersonProps) -> Html {
+    let OnePersonProps {
+        id,
+        client,
+        can_write,
+    } = (*props).clone();
+    let is_inserting = id.is_none();
+    let navigator = use_navigator().unwrap();
+    // The filter of the list to get back to.
+    let query = use_persons_query();
+    let on_back = Callback::from(move |_| {
+        let _ = navigator.push_with_query(&Route::Persons, &query);
+    });
+
+    // The version of the loaded person, on which the update is based.
+    let version: UseStateHandle<Option<u32>> = use_state(|| None);
+    let name = use_state(|| AttrValue::from(""));
+    let email = use_state(|| AttrValue::from(""));
+    let phone = use_state(|| AttrValue::from(""));
+    let loading = use_state(|| id.is_some());
+    let error: UseStateHandle<Option<String>> = use_state(|| None);
+
+    {
+        let version = version.clone();
+        let name = name.clone();
+        let email = email.clone();
+        let phone = phone.clone();
+        let loading = loading.clone();
+        let error = error.clone();
+        let client = client.clone();
+        use_effect_with(id, move |id| {
+            if let Some(id) = *id {
+                yew::platform::spawn_local(async move {
+                    match client.get_person(id).await {
+                        Ok(p) => {
+                            version.set(Some(p.version));
+                            name.set(p.name.into());
+                            email.set(p.email.unwrap_or_default().into());
+                            phone.set(p.phone.unwrap_or_default().into());
+                        }
+                        Err(e) => error.set(Some(e.to_string())),
+                    }
+                    loading.set(false);
+                });
+            }
+        });
+    }
+
+    let on_change = |field: &UseStateHandle<AttrValue>| {
+        let field = field.clone();
+        move |e: Event| {
+            field.set(AttrValue::from(
+                e.target_unchecked_into::<HtmlInputElement>().value(),
+            ))
+        }
+    };
+
+    let on_save = {
+        let version = version.clone();
+        let name = name.clone();
+        let email = email.clone();
+        let phone = phone.clone();
+        let loading = loading.clone();
+        let error = error.clone();
+        let on_back = on_back.clone();
+        Callback::from(move |_| {
+            let version = *version;
+            let name = name.trim().to_string();
+            let email = optional(&email);
+            let phone = optional(&phone);
+            let loading = loading.clone();
+            let error = error.clone();
+            let on_back = on_back.clone();
+            let client = client.clone();
+            loading.set(true);
+            error.set(None);
+            yew::platform::spawn_local(async move {
+                let result = match (id, version) {
+                    // The fields not shown, like the tags, are left untouched.
+                    (Some(id), Some(version)) => client
+                        .patch_person(
+                            &PersonPatch {
+                                id,
+                                name: Some(name),
+                                email: Some(email),
+                                phone: Some(phone),
+                                ..PersonPatch::default()
+                            },
+                            version,
+                        )
+                        .await
+                        .map(|_| ()),
+                    (Some(_), None) => Err(ClientError::Decode(
+                        "the person has not been loaded".to_string(),
+                    )),
+                    (None, _) => client
+                        .insert_person(&InsertingPerson {
+                            name,
+                            email,
+                            phone,
+                            ..InsertingPerson::default()
+                        })
+                        .await
+                        .map(|_| ()),
+                };
+                loading.set(false);
+                match result {
+                    Ok(()) => on_back.emit(()),
+                    Err(e) => error.set(Some(e.to_string())),
+                }
+            });
+        })
+    };
+
+    let on_cancel = move |_| on_back.emit(());
+    let read_only = !can_write || *loading;
+
+    html! {
+        <div>
+            <div>
+                <label>{ "Id: " }</label>
+                <input
+                    type="number"
+                    disabled=true
+                    value={id.map(|id| id.to_string()).unwrap_or_default()}
+                />
+            </div>
+            <div>
+                <label>{ "Name: " }</label>
+                <input
+                    value={(*name).clone()}
+                    onchange={on_change(&name)}
+                    disabled={read_only}
+                />
+            </div>
+            <div>
+                <label>{ "Email: " }</label>
+                <input
+                    type="email"
+                    value={(*email).clone()}
+                    onchange={on_change(&email)}
+                    disabled={read_only}
+                />
+            </div>
+            <div>
+                <label>{ "Phone: " }</label>
+                <input
+                    value={(*phone).clone()}
+                    onchange={on_change(&phone)}
+                    disabled={read_only}
+                />
+            </div>
+            if *loading {
+                <p>{ if is_inserting || version.is_some() { "Saving..." } else { "Loading..." } }</p>
+            }
+            if let Some(error) = (*error).clone() {
+                <p class="error">{ error }</p>
+            }
+            <div>
+                if can_write {
+                    <button onclick={on_save} disabled={*loading}>
+                        { if is_inserting { "Insert" } else { "Update" } }
+                    </button>
+                    { " " }
+                }
+                <button onclick={on_cancel}>{ if can_write { "Cancel" } else { "Back" } }</button>
+            </div>
+        </div>
+    }
+}
@@ -1,31 +1,274 @@
+use std::collections::HashSet;
+
+use persons_api::client::Client;
+use persons_api::models::{ChangeEvent, ChangeKind, DeleteOutcome, Person};
+use persons_ui::routes::{use_persons_query, PersonsQuery, Route};
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 use yew_hooks::prelude::*;
+use yew_router::prelude::*;
+
+use crate::events::use_person_events;
 
-use crate::common::Person;
+#[derive(PartialEq, Clone, Properties)]
+pub struct PersonsListProps {
+    pub client: Client,
+    pub can_write: bool,
+}
 
 #[function_component]
-pub fn PersonsList() -> Html {
+pub fn PersonsList(props: &PersonsListProps) -> Html {
+    let PersonsListProps { client, can_write } = (*props).clone();
+    let navigator = use_navigator().unwrap();
+    // The shown persons are those selected by the query of the URL.
+    let query = use_persons_query();
+
     let filtered_persons = use_list::<Person>(vec![]);
+    let id_to_find: UseStateHandle<Option<u32>> = use_state(|| None);
+    let name_portion = use_state(|| AttrValue::from(""));
+    let selected_ids = use_set(HashSet::<u32>::new());
+    let loading = use_state(|| false);
+    let error: UseStateHandle<Option<String>> = use_state(|| None);
+
+    // Replaces the shown persons with the one with the given id,
+    // or else with those whose name contains the given portion.
+    let load_persons = {
+        let filtered_persons = filtered_persons.clone();
+        let selected_ids = selected_ids.clone();
+        let loading = loading.clone();
+        let error = error.clone();
+        let client = client.clone();
+        move |query: PersonsQuery| {
+            let filtered_persons = filtered_persons.clone();
+            let selected_ids = selected_ids.clone();
+            let loading = loading.clone();
+            let error = error.clone();
+            let client = client.clone();
+            loading.set(true);
+            error.set(None);
+            yew::platform::spawn_local(async move {
+                let result = match query.id {
+                    Some(id) => client.get_person(id).await.map(|person| vec![person]),
+                    None => {
+                        client
+                            .get_persons(query.partial_name.as_deref().unwrap_or_default())
+                            .await
+                    }
+                };
+                selected_ids.clear();
+                match result {
+                    Ok(persons) => filtered_persons.set(persons),
+                    Err(e) => {
+                        filtered_persons.set(vec![]);
+                        error.set(Some(e.to_string()));
+                    }
+                }
+                loading.set(false);
+            });
+        }
+    };
+
+    // Also run when the URL is changed by the browser history.
+    {
+        let load_persons = load_persons.clone();
+        let id_to_find = id_to_find.clone();
+        let name_portion = name_portion.clone();
+        use_effect_with(query.clone(), move |query| {
+            id_to_find.set(query.id);
+            name_portion.set(query.partial_name.clone().unwrap_or_default().into());
+            load_persons(query.clone());
+        });
+    }
+
+    // The shown persons follow the changes made meanwhile, by this user or by others,
+    // without being refreshed manually.
+    {
+        let on_change = {
+            let filtered_persons = filtered_persons.clone();
+            let selected_ids = selected_ids.clone();
+            let load_persons = load_persons.clone();
+            let query = query.clone();
+            Callback::from(
+                move |change: ChangeEvent| match (change.kind, change.person) {
+                    (ChangeKind::Delete, _) => {
+                        filtered_persons.retain(|p| p.id != change.id);
+                        selected_ids.remove(&change.id);
+                    }
+                    (ChangeKind::Update, Some(person)) => {
+                        let index = filtered_persons
+                            .current()
+                            .iter()
+                            .position(|p| p.id == person.id);
+                        if let Some(index) = index {
+                            filtered_persons.update(index, person);
+                        }
+                    }
+                    // A new person may be selected by the query.
+                    _ => load_persons(query.clone()),
+                },
+            )
+        };
+        let on_reset = {
+            let load_persons = load_persons.clone();
+            let query = query.clone();
+            Callback::from(move |_| load_persons(query.clone()))
+        };
+        use_person_events(client.events_url(None), on_change, on_reset);
+    }
+
+    // Changing the URL reloads the list, unless it is unchanged.
+    let show = {
+        let load_persons = load_persons.clone();
+        let navigator = navigator.clone();
+        let query = query.clone();
+        move |new_query: PersonsQuery| {
+            if new_query == query {
+                load_persons(new_query);
+            } else {
+                let _ = navigator.push_with_query(&Route::Persons, &new_query);
+            }
+        }
+    };
+
+    let on_change_id = {
+        let id_to_find = id_to_find.clone();
+        move |e: Event| {
+            id_to_find.set(
+                e.target_unchecked_into::<HtmlInputElement>()
+                    .value()
+                    .trim()
+                    .parse()
+                    .ok(),
+            );
+        }
+    };
+
+    let on_change_name_portion = {
+        let name_portion = name_portion.clone();
+        move |e: Event| {
+            name_portion.set(e.target_unchecked_into::<HtmlInputElement>().value().into());
+        }
+    };
+
+    let on_find = {
+        let show = show.clone();
+        let id_to_find = id_to_find.clone();
+        let error = error.clone();
+        move |_| match *id_to_find {
+            Some(id) => show(PersonsQuery {
+                partial_name: None,
+                id: Some(id),
+            }),
+            None => error.set(Some("Enter the id of the person to find.".to_string())),
+        }
+    };
+
+    let on_filter = {
+        let show = show.clone();
+        let name_portion = name_portion.clone();
+        move |_| {
+            show(PersonsQuery {
+                partial_name: Some(name_portion.to_string()).filter(|portion| !portion.is_empty()),
+                id: None,
+            })
+        }
+    };
+
+    let on_add = {
+        let navigator = navigator.clone();
+        let query = query.clone();
+        move |_| {
+            let _ = navigator.push_with_query(&Route::NewPerson, &query);
+        }
+    };
+
+    let on_delete = {
+        let load_persons = load_persons.clone();
+        let query = query.clone();
+        let filtered_persons = filtered_persons.clone();
+        let selected_ids = selected_ids.clone();
+        let loading = loading.clone();
+        let error = error.clone();
+        let client = client.clone();
+        move |_| {
+            // The versions shown, so that the persons changed meanwhile are not deleted.
+            let selected = selected_ids.current();
+            let versions = filtered_persons
+                .current()
+                .iter()
+                .filter(|p| selected.contains(&p.id))
+                .map(|p| (p.id, p.version))
+                .collect::<Vec<_>>();
+            if versions.is_empty() {
+                error.set(Some("No persons selected.".to_string()));
+                return;
+            }
+            let load_persons = load_persons.clone();
+            let query = query.clone();
+            let loading = loading.clone();
+            let error = error.clone();
+            let client = client.clone();
+            loading.set(true);
+            error.set(None);
+            yew::platform::spawn_local(async move {
+                match client.delete_persons(&versions).await {
+                    // Reloaded also when some were already deleted by someone else.
+                    Ok(report) => {
+                        let changed = report
+                            .results
+                            .iter()
+                            .filter(|r| r.outcome == DeleteOutcome::PreconditionFailed)
+                            .count();
+                        load_persons(query);
+                        if changed > 0 {
+                            error.set(Some(format!(
+                                "{} of the selected persons have been modified meanwhile, \
+                                 and have not been deleted.",
+                                changed
+                            )));
+                        }
+                    }
+                    Err(e) => {
+                        error.set(Some(e.to_string()));
+                        loading.set(false);
+                    }
+                }
+            });
+        }
+    };
 
     html! {
         <div>
             <div>
                 <label>{ "Id: " }</label>
-                <input type="number" />
+                <input
+                    type="number"
+                    value={id_to_find.map(|id| id.to_string()).unwrap_or_default()}
+                    onchange={on_change_id}
+                />
                 { " " }
-                <button>{ "Find" }</button>
+                <button onclick={on_find} disabled={*loading}>{ "Find" }</button>
             </div>
             <div>
                 <label>{ "Name portion: " }</label>
-                <input />
+                <input value={(*name_portion).clone()} onchange={on_change_name_portion} />
                 { " " }
-                <button>{ "Filter" }</button>
+                <button onclick={on_filter} disabled={*loading}>{ "Filter" }</button>
             </div>
-            <button>{ "Delete Selected Persons" }</button>
+            <button onclick={on_delete} disabled={*loading || !can_write}>
+                { "Delete Selected Persons" }
+            </button>
             { " " }
-            <button>{ "Add New Person" }</button>
+            <button onclick={on_add} disabled={!can_write}>{ "Add New Person" }</button>
+            if let Some(error) = (*error).clone() {
+                <p class="error">{ error }</p>
+            }
             {
-                if filtered_persons.current().is_empty() {
+                if *loading {
+                    html! {
+                        <p>{ "Loading..." }</p>
+                    }
+                } else if filtered_persons.current().is_empty() {
                     html! {
                         <p>{ "No persons." }</p>
                     }
@@ -37,22 +280,49 @@ pub fn PersonsList() -> Html {
                                 <th></th>
                                 <th>{ "Id" }</th>
                                 <th>{ "Name" }</th>
+                                <th>{ "Email" }</th>
+                                <th>{ "Phone" }</th>
                             </thead>
                             <tbody>
                             {
                                 for filtered_persons.current().iter().map(|p| {
-                                    let id = p.id.clone();
-                                    let name = p.name.clone();
+                                    let id = p.id;
+                                    let on_checked = {
+                                        let selected_ids = selected_ids.clone();
+                                        move |e: Event| {
+                                            if e.target_unchecked_into::<HtmlInputElement>().checked() {
+                                                selected_ids.insert(id);
+                                            } else {
+                                                selected_ids.remove(&id);
+                                            }
+                                        }
+                                    };
+                                    let on_edit = {
+                                        let navigator = navigator.clone();
+                                        let query = query.clone();
+                                        move |_| {
+                                            let _ = navigator.push_with_query(&Route::EditPerson { id }, &query);
+                                        }
+                                    };
                                     html! {
                                         <tr>
                                             <td>
-                                                <input type="checkbox" />
+                                                <input
+                                                    type="checkbox"
+                                                    onchange={on_checked}
+                                                    checked={selected_ids.current().contains(&id)}
+                                                    disabled={!can_write}
+                                                />
                                             </td>
                                             <td>
-                                                <button>{ "Edit" }</button>
+                                                <button onclick={on_edit}>
+                                                    { if can_write { "Edit" } else { "View" } }
+                                                </button>
                                             </td>
                                             <td>{ id.to_string() }</td>
-                                            <td>{ name }</td>
+                                            <td>{ p.name.clone() }</td>
+                                            <td>{ p.email.clone().unwrap_or_default() }</td>
+                                            <td>{ p.phone.clone().unwrap_or_default() }</td>
                                         </tr>
                                     }
                                 })